@@ -0,0 +1,229 @@
+use crate::calc::Attack;
+use std::collections::{HashSet, VecDeque};
+
+/// Offset coordinates `(col, row)` on a pointy-top grid where odd rows are shifted right.
+pub type Hex = (i32, i32);
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct Battlefield {
+    pub width: i32,
+    pub height: i32,
+    pub obstacles: Vec<Hex>,
+    /// Ranged attacks further than this take the long range penalty.
+    pub long_range_distance: i32,
+}
+
+impl Default for Battlefield {
+    fn default() -> Self {
+        Self {
+            width: 15,
+            height: 11,
+            obstacles: Vec::new(),
+            long_range_distance: 10,
+        }
+    }
+}
+
+fn to_cube((col, row): Hex) -> (i32, i32, i32) {
+    let q = col - (row - (row & 1)) / 2;
+    (q, row, -q - row)
+}
+
+fn from_cube((q, r, _): (i32, i32, i32)) -> Hex {
+    (q + (r - (r & 1)) / 2, r)
+}
+
+fn cube_round((x, y, z): (f32, f32, f32)) -> (i32, i32, i32) {
+    let (mut q, mut r, s) = (x.round(), y.round(), z.round());
+    let (dq, dr, ds) = ((q - x).abs(), (r - y).abs(), (s - z).abs());
+    if dq > dr && dq > ds {
+        q = -r - s;
+    } else if dr > ds {
+        r = -q - s;
+    }
+    (q as i32, r as i32, (-q - r) as i32)
+}
+
+pub fn distance(a: Hex, b: Hex) -> i32 {
+    let (a, b) = (to_cube(a), to_cube(b));
+    ((a.0 - b.0).abs() + (a.1 - b.1).abs() + (a.2 - b.2).abs()) / 2
+}
+
+pub fn neighbors(hex: Hex) -> [Hex; 6] {
+    let (q, r, s) = to_cube(hex);
+    [
+        (q + 1, r, s - 1),
+        (q - 1, r, s + 1),
+        (q, r + 1, s - 1),
+        (q, r - 1, s + 1),
+        (q + 1, r - 1, s),
+        (q - 1, r + 1, s),
+    ]
+    .map(from_cube)
+}
+
+/// Hexes on the straight line from `a` to `b`, both ends included.
+pub fn line(a: Hex, b: Hex) -> Vec<Hex> {
+    let n = distance(a, b);
+    let (a, b) = (to_cube(a), to_cube(b));
+    // nudge off the exact edges so ties round the same way every time
+    let (ax, ay, az) = (a.0 as f32 + 1e-6, a.1 as f32 + 1e-6, a.2 as f32 - 2e-6);
+    (0..=n)
+        .map(|i| {
+            let t = if n == 0 { 0.0 } else { i as f32 / n as f32 };
+            from_cube(cube_round((
+                ax + (b.0 as f32 - ax) * t,
+                ay + (b.1 as f32 - ay) * t,
+                az + (b.2 as f32 - az) * t,
+            )))
+        })
+        .collect()
+}
+
+impl Battlefield {
+    pub fn contains(&self, (col, row): Hex) -> bool {
+        (0..self.width).contains(&col) && (0..self.height).contains(&row)
+    }
+
+    pub fn is_obstacle(&self, hex: Hex) -> bool {
+        self.obstacles.contains(&hex)
+    }
+
+    pub fn toggle_obstacle(&mut self, hex: Hex) {
+        if let Some(i) = self.obstacles.iter().position(|h| *h == hex) {
+            self.obstacles.remove(i);
+        } else {
+            self.obstacles.push(hex);
+        }
+    }
+
+    /// Every hex reachable from `from` in at most `speed` steps, walking around
+    /// obstacles and the `occupied` hexes. Nothing is reachable at a speed of 0 or less.
+    pub fn reachable(&self, from: Hex, speed: i32, occupied: &[Hex]) -> HashSet<Hex> {
+        let mut seen = HashSet::from([from]);
+        let mut queue = VecDeque::from([(from, 0)]);
+        while let Some((hex, steps)) = queue.pop_front() {
+            if steps >= speed {
+                continue;
+            }
+            for next in neighbors(hex) {
+                if self.contains(next)
+                    && !self.is_obstacle(next)
+                    && !occupied.contains(&next)
+                    && seen.insert(next)
+                {
+                    queue.push_back((next, steps + 1));
+                }
+            }
+        }
+        seen.remove(&from);
+        seen
+    }
+
+    /// Whether no obstacle stands between the two hexes.
+    pub fn line_of_sight(&self, a: Hex, b: Hex) -> bool {
+        let line = line(a, b);
+        line.iter()
+            .skip(1)
+            .take(line.len().saturating_sub(2))
+            .all(|hex| !self.is_obstacle(*hex))
+    }
+
+    /// Sets the range penalties of `attack` from the positions of the two units.
+    /// Units that are not on the field are not checked at all.
+    pub fn check_attack(
+        &self,
+        attacker: Option<Hex>,
+        defender: Option<Hex>,
+        ranged: bool,
+        attack: &mut Attack,
+    ) -> Result<(), &'static str> {
+        let (Some(attacker), Some(defender)) = (attacker, defender) else {
+            return Ok(());
+        };
+        if ranged {
+            attack.obstacle |= !self.line_of_sight(attacker, defender);
+            attack.long_range |= distance(attacker, defender) > self.long_range_distance;
+            Ok(())
+        } else if distance(attacker, defender) == 1 {
+            Ok(())
+        } else {
+            Err("not adjacent")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_and_neighbors() {
+        assert_eq!(distance((0, 0), (3, 0)), 3);
+        assert_eq!(distance((0, 0), (0, 2)), 2);
+        assert_eq!(distance((1, 1), (1, 0)), 1);
+        for hex in [(2, 2), (3, 3)] {
+            for n in neighbors(hex) {
+                assert_eq!(distance(hex, n), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn movement_walks_around_obstacles() {
+        let field = Battlefield {
+            width: 5,
+            height: 1,
+            obstacles: vec![(2, 0)],
+            ..Default::default()
+        };
+        let reachable = field.reachable((0, 0), 10, &[]);
+        assert_eq!(reachable, HashSet::from([(1, 0)]));
+
+        let field = Battlefield {
+            width: 5,
+            height: 3,
+            obstacles: vec![(2, 0), (2, 1)],
+            ..Default::default()
+        };
+        assert!(!field.reachable((0, 0), 3, &[]).contains(&(3, 0)));
+        assert!(field.reachable((0, 0), 6, &[]).contains(&(3, 0)));
+        assert!(!field
+            .reachable((0, 0), 6, &[(2, 2), (1, 2)])
+            .contains(&(3, 0)));
+        // Slowed or bogged down below zero.
+        assert!(field.reachable((0, 0), 0, &[]).is_empty());
+        assert!(field.reachable((0, 0), -2, &[]).is_empty());
+    }
+
+    #[test]
+    fn attack_checks() {
+        let field = Battlefield {
+            obstacles: vec![(2, 0)],
+            long_range_distance: 3,
+            ..Default::default()
+        };
+        let mut attack = Attack::default();
+        assert!(field
+            .check_attack(Some((0, 0)), Some((1, 0)), false, &mut attack)
+            .is_ok());
+        assert!(field
+            .check_attack(Some((0, 0)), Some((4, 0)), false, &mut attack)
+            .is_err());
+        assert!(field
+            .check_attack(None, Some((4, 0)), false, &mut attack)
+            .is_ok());
+
+        field
+            .check_attack(Some((0, 0)), Some((3, 0)), true, &mut attack)
+            .unwrap();
+        assert!(attack.obstacle && !attack.long_range);
+
+        let mut attack = Attack::default();
+        field
+            .check_attack(Some((0, 1)), Some((5, 1)), true, &mut attack)
+            .unwrap();
+        assert!(!attack.obstacle && attack.long_range);
+    }
+}
@@ -0,0 +1,293 @@
+#![warn(clippy::all, rust_2018_idioms)]
+//! Resolves one attack without the GUI.
+//!
+//! damage_calc_cli <classes.json> <attacker class> <defender class> [options]
+
+use damage_calc_core::calc::{Attack, AttackMode, Calc, Stats, Strike, Unit};
+use std::process::ExitCode;
+use std::str::FromStr;
+
+const USAGE: &str =
+    "usage: damage_calc_cli <classes.json> <attacker class> <defender class> [options]
+  --attacker-count N     attacking stack size (default 1)
+  --defender-count N     defending stack size (default 1)
+  --attacker-bonus LIST  unit bonuses like atk=2,def=-1,hp=5
+  --defender-bonus LIST  keys: atk def hp min_dmg max_dmg luck lead mor spd
+  --percent N            damage percent (default 100)
+  --retaliation          let the defender strike back
+  --ranged               shoot instead of melee
+  --seed N               repeatable rolls
+  --runs N               simulate N attacks and print the summary
+  --json                 print json instead of text";
+
+struct Args {
+    classes: String,
+    attacker: Unit,
+    defender: Unit,
+    attack: Attack,
+    seed: Option<u64>,
+    runs: Option<u32>,
+    json: bool,
+}
+
+fn parse_bonus(list: &str) -> Result<Stats, String> {
+    let mut stats = Stats::default();
+    for pair in list.split(',').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("bonus `{}` is not key=value", pair))?;
+        let value = value
+            .parse::<i32>()
+            .map_err(|_| format!("bonus `{}` is not a number", pair))?;
+        let field = match key {
+            "atk" => &mut stats.attack,
+            "def" => &mut stats.defense,
+            "hp" => &mut stats.health,
+            "min_dmg" => &mut stats.min_dmg,
+            "max_dmg" => &mut stats.max_dmg,
+            "luck" => &mut stats.luck,
+            "lead" => &mut stats.leadership,
+            "mor" => &mut stats.morale,
+            "spd" => &mut stats.speed,
+            _ => return Err(format!("unknown bonus `{}`", key)),
+        };
+        *field = value;
+    }
+    Ok(stats)
+}
+
+fn number<T: FromStr>(name: &str, value: String) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("{} needs a number, got `{}`", name, value))
+}
+
+fn count(name: &str, value: String) -> Result<i32, String> {
+    match number::<i32>(name, value)? {
+        count if count >= 1 => Ok(count),
+        count => Err(format!("{} must be at least 1, got {}", name, count)),
+    }
+}
+
+/// `None` when only the usage was asked for.
+fn parse_args(args: Vec<String>) -> Result<Option<Args>, String> {
+    let mut positional = Vec::new();
+    let mut options = args.into_iter();
+    let mut result = Args {
+        classes: String::new(),
+        attacker: Unit::default(),
+        defender: Unit::default(),
+        attack: Attack::default(),
+        seed: None,
+        runs: None,
+        json: false,
+    };
+    let (mut attacker_count, mut defender_count) = (1, 1);
+    while let Some(arg) = options.next() {
+        let mut value = |name: &str| {
+            options
+                .next()
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match arg.as_str() {
+            "--attacker-count" => attacker_count = count(&arg, value(&arg)?)?,
+            "--defender-count" => defender_count = count(&arg, value(&arg)?)?,
+            "--attacker-bonus" => result.attacker.stats = parse_bonus(&value(&arg)?)?,
+            "--defender-bonus" => result.defender.stats = parse_bonus(&value(&arg)?)?,
+            "--percent" => result.attack.percent = number(&arg, value(&arg)?)?,
+            "--retaliation" => result.attack.retaliation = true,
+            "--ranged" => result.attack.mode = AttackMode::Ranged,
+            "--seed" => result.seed = Some(number(&arg, value(&arg)?)?),
+            "--runs" => result.runs = Some(number(&arg, value(&arg)?)?),
+            "--json" => result.json = true,
+            "-h" | "--help" => return Ok(None),
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+            _ => positional.push(arg),
+        }
+    }
+    let [classes, attacker, defender] = <[String; 3]>::try_from(positional)
+        .map_err(|_| "expected a class file, an attacker and a defender".to_string())?;
+    result.classes = classes;
+    result.attacker.name = attacker;
+    result.attacker.value = attacker_count;
+    result.attacker.initial = attacker_count;
+    result.defender.name = defender;
+    result.defender.value = defender_count;
+    result.defender.initial = defender_count;
+    Ok(Some(result))
+}
+
+fn strike_line(strike: &Strike) -> String {
+    let mut line = format!(
+        "{}{} dmg, {} killed",
+        if strike.retaliation {
+            "retaliation: "
+        } else {
+            "strike: "
+        },
+        strike.damage,
+        strike.killed
+    );
+    if !strike.breakdown.is_empty() {
+        line += &format!(" ({})", strike.breakdown.join(", "));
+    }
+    line
+}
+
+fn run(args: Args) -> Result<String, String> {
+    let json = std::fs::read_to_string(&args.classes)
+        .map_err(|e| format!("can't read {}: {}", args.classes, e))?;
+    let mut calc = Calc::default();
+    match calc.update(&json) {
+        -1 => return Err(format!("{} is not a class file", args.classes)),
+        0 => {}
+        errs => eprintln!("{} classes could not be read", errs),
+    }
+    for unit in [&args.attacker, &args.defender] {
+        if !calc.classes.contains_key(&unit.name) {
+            return Err(format!("no class `{}` in {}", unit.name, args.classes));
+        }
+    }
+    if let Some(seed) = args.seed {
+        calc.seed(seed);
+    }
+    let (mut attacker, mut defender) = (args.attacker, args.defender);
+
+    if let Some(runs) = args.runs {
+        let simulation = calc.simulate(&defender, &attacker, &args.attack, runs);
+        return Ok(if args.json {
+            serde_json::to_string_pretty(&simulation).unwrap_or_default()
+        } else {
+            format!(
+                "{} x{} attacks {} x{}, {} runs\n\
+                 damage: min {}, avg {:.1}, max {}\n\
+                 killed: avg {:.1}\n\
+                 retaliation: avg {:.1} dmg, {:.1} killed\n\
+                 defender wiped out: {:.0}%",
+                attacker.name,
+                attacker.value,
+                defender.name,
+                defender.value,
+                simulation.runs,
+                simulation.min_damage,
+                simulation.avg_damage,
+                simulation.max_damage,
+                simulation.avg_killed,
+                simulation.avg_retaliation_damage,
+                simulation.avg_retaliation_killed,
+                simulation.defender_wiped * 100.0
+            )
+        });
+    }
+
+    let title = format!(
+        "{} x{} attacks {} x{}",
+        attacker.name, attacker.value, defender.name, defender.value
+    );
+    let strikes = calc.calculate(&mut defender, &mut attacker, &args.attack);
+    Ok(if args.json {
+        serde_json::to_string_pretty(&serde_json::json!({
+            "strikes": strikes,
+            "attacker": {"value": attacker.value, "damage_left": attacker.damage_left},
+            "defender": {"value": defender.value, "damage_left": defender.damage_left},
+        }))
+        .unwrap_or_default()
+    } else {
+        let mut lines = vec![title];
+        lines.extend(strikes.iter().map(strike_line));
+        lines.push(format!(
+            "{} left: {}, {} left: {}",
+            attacker.name, attacker.value, defender.name, defender.value
+        ));
+        lines.join("\n")
+    })
+}
+
+fn main() -> ExitCode {
+    let result = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(Some(args)) => run(args),
+        Ok(None) => Ok(USAGE.to_string()),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(output) => {
+            println!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Option<Args>, String> {
+        parse_args(args.split_whitespace().map(str::to_string).collect())
+    }
+
+    #[test]
+    fn arguments() {
+        let args = parse("classes.json Peasant Angel --attacker-count 50 --retaliation --ranged")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (args.attacker.name.as_str(), args.attacker.value),
+            ("Peasant", 50)
+        );
+        assert_eq!(
+            (args.defender.name.as_str(), args.defender.initial),
+            ("Angel", 1)
+        );
+        assert!(args.attack.retaliation && args.attack.mode == AttackMode::Ranged);
+        let args = parse("c.json A B --defender-bonus atk=2,hp=-1 --runs 10 --seed 3")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (args.defender.stats.attack, args.defender.stats.health),
+            (2, -1)
+        );
+        assert_eq!((args.runs, args.seed), (Some(10), Some(3)));
+
+        assert!(parse("--help").unwrap().is_none());
+        assert!(parse("c.json A B -h").unwrap().is_none());
+        assert!(parse("c.json A").is_err());
+        assert!(parse("c.json A B --bogus").is_err());
+        assert!(parse("c.json A B --percent").is_err());
+        assert!(parse("c.json A B --attacker-bonus speed=1").is_err());
+        for count in ["0", "-5", "2147483648", "many"] {
+            assert!(parse(&format!("c.json A B --attacker-count {}", count)).is_err());
+        }
+        assert!(parse("c.json A B --runs -1").is_err());
+    }
+
+    #[test]
+    fn largest_counts() {
+        let mut calc = Calc::default();
+        calc.update(
+            r#"{
+                "Peasant": {"attack": 1, "defence": 1, "min_dmg": 1, "max_dmg": 3, "health": 3, "description": ""},
+                "Angel": {"attack": 20, "defence": 20, "min_dmg": 50, "max_dmg": 50, "health": 200, "description": ""}
+            }"#,
+        );
+        let max = i32::MAX;
+        let args = format!(
+            "c.json Angel Peasant --attacker-count {} --defender-count {} --retaliation",
+            max, max
+        );
+        let args = parse(&args).unwrap().unwrap();
+        let (mut attacker, mut defender) = (args.attacker, args.defender);
+        let strikes = calc.calculate(&mut defender, &mut attacker, &args.attack);
+        assert_eq!((strikes[0].damage, strikes[0].killed), (max, max));
+        assert_eq!(defender.value, 0);
+
+        let args = format!("c.json Peasant Angel --attacker-count {}", max);
+        let args = parse(&args).unwrap().unwrap();
+        let simulation = calc.simulate(&args.defender, &args.attacker, &args.attack, 5);
+        assert!(simulation.min_damage > 1_000_000_000);
+        assert_eq!(simulation.avg_killed, 1.0);
+    }
+}
@@ -0,0 +1,291 @@
+#![warn(clippy::all, rust_2018_idioms)]
+//! Runs a battle from the terminal, one command per line.
+//!
+//! damage_calc_repl [session.json]
+
+use damage_calc_core::calc::Unit;
+use damage_calc_core::session::{Session, Team};
+use std::io::{BufRead, Write};
+
+const HELP: &str = "commands:
+  classes FILE            load a class file
+  spells FILE             load a spells file
+  team NAME [SLOTS]       add a team (default 7 slots)
+  add TEAM CLASS COUNT    put a stack in the first empty slot, e.g. add A Peasant 20
+  remove SLOT             empty a slot, e.g. remove B2
+  retaliation TEAM on|off let the team strike back
+  attack ATTACKER DEFENDER  e.g. attack A3 B1
+  next                    start a new round
+  status                  show the teams
+  log [N]                 show the last N log lines (default 10)
+  undo                    revert the last change
+  save FILE / load FILE   the same session json as the app
+  quit";
+
+#[derive(Default)]
+struct Repl {
+    session: Session,
+    history: Vec<Session>,
+}
+
+fn read(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))
+}
+
+impl Repl {
+    fn status(&self) -> String {
+        let mut lines = vec![format!("round {}", self.session.round)];
+        for (t, team) in self.session.teams.iter().enumerate() {
+            lines.push(format!(
+                "{} {}{}",
+                Session::address((t, 0)).trim_end_matches('0'),
+                team.name,
+                if team.retaliation {
+                    " (retaliates)"
+                } else {
+                    ""
+                }
+            ));
+            for (slot, unit) in team.units.iter().enumerate() {
+                if let Some(unit) = unit {
+                    let health = self
+                        .session
+                        .calc
+                        .effective_stats(unit, &team.hero_bonus())
+                        .map(|stats| stats.health)
+                        .unwrap_or_default();
+                    lines.push(format!(
+                        "  {} {} {}/{} alive, top {}/{} hp",
+                        Session::address((t, slot)),
+                        unit.name,
+                        unit.value,
+                        unit.initial,
+                        health - unit.damage_left,
+                        health
+                    ));
+                }
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Runs one command, `Ok(None)` means quit.
+    fn run(&mut self, line: &str) -> Result<Option<String>, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(Some(String::new()));
+        };
+        let before = self.session.clone();
+        let output = match (command, args) {
+            ("help", _) => return Ok(Some(HELP.to_string())),
+            ("quit" | "exit", _) => return Ok(None),
+            ("status", []) => return Ok(Some(self.status())),
+            ("log", args) => {
+                let count = match args {
+                    [] => 10,
+                    [count] => count
+                        .parse::<usize>()
+                        .map_err(|_| format!("`{}` is not a number", count))?,
+                    _ => return Err("usage: log [N]".to_string()),
+                };
+                let log = &self.session.log;
+                return Ok(Some(log[log.len().saturating_sub(count)..].join("\n")));
+            }
+            ("undo", []) => {
+                self.session = self.history.pop().ok_or("nothing to undo")?;
+                return Ok(Some("undone".to_string()));
+            }
+            ("classes", [path]) => match self.session.calc.update(&read(path)?) {
+                -1 => return Err(format!("{} is not a class file", path)),
+                errs => format!(
+                    "{} classes, {} errors",
+                    self.session.calc.classes.len(),
+                    errs
+                ),
+            },
+            ("spells", [path]) => match self.session.calc.update_spells(&read(path)?) {
+                -1 => return Err(format!("{} is not a spells file", path)),
+                errs => format!("{} spells, {} errors", self.session.calc.spells.len(), errs),
+            },
+            ("team", [name, args @ ..]) => {
+                let slots = match args {
+                    [] => 7,
+                    [slots] => slots
+                        .parse::<usize>()
+                        .map_err(|_| format!("`{}` is not a number", slots))?,
+                    _ => return Err("usage: team NAME [SLOTS]".to_string()),
+                };
+                let mut team = Team::new(slots);
+                team.name = name.to_string();
+                self.session.teams.push(team);
+                format!(
+                    "team {}",
+                    Session::address((self.session.teams.len() - 1, 0)).trim_end_matches('0')
+                )
+            }
+            ("add", [team, class, count]) => {
+                let t = self.session.parse_team(team)?;
+                if !self.session.calc.classes.contains_key(*class) {
+                    return Err(format!("no class `{}`", class));
+                }
+                let count = match count.parse::<i32>() {
+                    Ok(count) if count >= 1 => count,
+                    Ok(count) => return Err(format!("count must be at least 1, got {}", count)),
+                    Err(_) => return Err(format!("`{}` is not a number", count)),
+                };
+                let unit = Unit {
+                    name: class.to_string(),
+                    value: count,
+                    initial: count,
+                    ..Default::default()
+                };
+                let units = &mut self.session.teams[t].units;
+                let slot = match units.iter().position(Option::is_none) {
+                    Some(slot) => slot,
+                    None => {
+                        units.push(None);
+                        units.len() - 1
+                    }
+                };
+                units[slot] = Some(unit);
+                self.session.label((t, slot))
+            }
+            ("remove", [address]) => {
+                let (t, slot) = self.session.parse_address(address)?;
+                let unit = self.session.teams[t]
+                    .units
+                    .get_mut(slot)
+                    .and_then(Option::take)
+                    .ok_or_else(|| format!("{} is empty", address))?;
+                format!("removed {}", unit.name)
+            }
+            ("retaliation", [team, switch]) => {
+                let t = self.session.parse_team(team)?;
+                self.session.teams[t].retaliation = match *switch {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err("usage: retaliation TEAM on|off".to_string()),
+                };
+                format!("{} retaliation {}", self.session.teams[t].name, switch)
+            }
+            ("attack", [attacker, defender]) => {
+                let attacker = self.session.parse_address(attacker)?;
+                let defender = self.session.parse_address(defender)?;
+                self.session.attack(attacker, defender)?;
+                self.session.log.last().cloned().unwrap_or_default()
+            }
+            ("next", []) => {
+                self.session.new_round();
+                format!("round {}", self.session.round)
+            }
+            ("save", [path]) => {
+                std::fs::write(path, self.session.to_json())
+                    .map_err(|e| format!("can't write {}: {}", path, e))?;
+                return Ok(Some(format!("saved {}", path)));
+            }
+            ("load", [path]) => {
+                self.session = Session::from_json(&read(path)?)?;
+                format!("loaded {}", path)
+            }
+            _ => return Err(format!("can't do `{}`, try help", line.trim())),
+        };
+        self.history.push(before);
+        Ok(Some(output))
+    }
+}
+
+fn main() {
+    let mut repl = Repl::default();
+    if let Some(path) = std::env::args().nth(1) {
+        match read(&path).and_then(|json| Session::from_json(&json)) {
+            Ok(session) => repl.session = session,
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    let stdin = std::io::stdin();
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        match repl.run(&line) {
+            Ok(Some(output)) if output.is_empty() => {}
+            Ok(Some(output)) => println!("{}", output),
+            Ok(None) => break,
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(repl: &mut Repl, line: &str) -> Result<String, String> {
+        repl.run(line).map(Option::unwrap_or_default)
+    }
+
+    #[test]
+    fn commands() {
+        let dir = std::env::temp_dir().join(format!("damage_calc_repl_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let classes = dir.join("classes.json");
+        std::fs::write(
+            &classes,
+            r#"{"Peasant": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": ""}}"#,
+        )
+        .unwrap();
+        let saved = dir.join("session.json");
+        let (classes, saved) = (classes.to_str().unwrap(), saved.to_str().unwrap());
+
+        let mut repl = Repl::default();
+        assert_eq!(
+            run(&mut repl, &format!("classes {}", classes)),
+            Ok("1 classes, 0 errors".to_string())
+        );
+        assert_eq!(run(&mut repl, "team red 2"), Ok("team A".to_string()));
+        assert_eq!(run(&mut repl, "team blue"), Ok("team B".to_string()));
+        assert!(run(&mut repl, "add a Peasant 10").is_ok());
+        assert!(run(&mut repl, "add B Peasant 10").is_ok());
+        for bad in [
+            "add A Peasant 0",
+            "add A Peasant -5",
+            "add A Peasant many",
+            "add A Angel 1",
+            "add C Peasant 1",
+            "add 1 Peasant 1",
+            "attack A0 C0",
+            "attack A0",
+            "remove B5",
+            "retaliation A maybe",
+            "fly A0",
+        ] {
+            assert!(run(&mut repl, bad).is_err(), "{}", bad);
+        }
+        assert_eq!(repl.session.teams[0].units.iter().flatten().count(), 1);
+
+        let attack = run(&mut repl, "attack A0 B0").unwrap();
+        assert!(attack.contains("10 dmg, 1 killed"), "{}", attack);
+        assert_eq!(repl.session.unit((1, 0)).unwrap().value, 9);
+        assert!(run(&mut repl, "save /no/such/dir/session.json").is_err());
+        assert_eq!(
+            run(&mut repl, &format!("save {}", saved)),
+            Ok(format!("saved {}", saved))
+        );
+
+        assert_eq!(run(&mut repl, "undo"), Ok("undone".to_string()));
+        assert_eq!(repl.session.unit((1, 0)).unwrap().value, 10);
+        assert!(run(&mut repl, &format!("load {}", saved)).is_ok());
+        assert_eq!(repl.session.unit((1, 0)).unwrap().value, 9);
+        for _ in 0..6 {
+            assert!(run(&mut repl, "undo").is_ok());
+        }
+        assert!(repl.session.teams.is_empty());
+        assert_eq!(run(&mut repl, "undo"), Err("nothing to undo".to_string()));
+        assert_eq!(repl.run("quit"), Ok(None));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,2127 @@
+use crate::battlefield::Hex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::cell::{RefCell, RefMut};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+
+/// How many times a unit may strike back per round without `UnlimitedRetaliation`.
+pub const RETALIATIONS_PER_ROUND: i32 = 1;
+
+/// Damage bonus against a hated class, in percent.
+pub const HATE_BONUS: i32 = 50;
+
+/// Attack, defence and speed bonus of a unit fighting on one of its native terrains.
+pub const NATIVE_TERRAIN_BONUS: i32 = 1;
+
+/// Declared in the class json as `"abilities": ["double_strike", {"ignore_defense": 40}]`.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Ability {
+    /// Strikes back every time it is attacked.
+    UnlimitedRetaliation,
+    /// Enemies never retaliate against this unit's attacks.
+    NoEnemyRetaliation,
+    /// Attacks twice; the second blow comes after the retaliation.
+    DoubleStrike,
+    /// Retaliates before the attacker's blow lands.
+    FirstStrike,
+    /// Ignores this percent of the defender's defence.
+    IgnoreDefense(i32),
+    /// Deals `HATE_BONUS` percent more damage to the named class.
+    Hates(String),
+    /// Can attack at range, but takes `Rules::melee_penalty` in melee.
+    Shooter,
+    /// Has no morale and lowers the morale of living units on its team.
+    Undead,
+}
+
+/// `"melee"` or `"ranged"`, the capitalised names of older saves still load.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AttackMode {
+    #[default]
+    #[serde(alias = "Melee")]
+    Melee,
+    #[serde(alias = "Ranged")]
+    Ranged,
+}
+
+/// Everything about an attack that is not a property of the units themselves.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct Attack {
+    pub percent: i32,
+    /// Whether the defender's side strikes back at all.
+    pub retaliation: bool,
+    /// Ignored unless the attacker is a `Shooter`.
+    pub mode: AttackMode,
+    pub long_range: bool,
+    pub obstacle: bool,
+    /// Bonus of the attacking side's hero, see `Hero::bonus`.
+    pub attacker_hero: Stats,
+    pub defender_hero: Stats,
+}
+
+impl Default for Attack {
+    fn default() -> Self {
+        Self {
+            percent: 100,
+            retaliation: false,
+            mode: AttackMode::Melee,
+            long_range: false,
+            obstacle: false,
+            attacker_hero: Stats::default(),
+            defender_hero: Stats::default(),
+        }
+    }
+}
+
+/// Penalties in percent of damage lost, morale penalties in percent of morale.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub struct Rules {
+    pub long_range_penalty: i32,
+    pub obstacle_penalty: i32,
+    pub melee_penalty: i32,
+    /// Morale lost for every faction on a team beyond the first.
+    pub mixed_faction_morale: i32,
+    /// Morale lost by living units fighting next to `Undead`.
+    pub undead_morale: i32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            long_range_penalty: 50,
+            obstacle_penalty: 50,
+            melee_penalty: 50,
+            mixed_faction_morale: 10,
+            undead_morale: 10,
+        }
+    }
+}
+
+/// Outcome of the morale roll at the start of a unit's turn.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Morale {
+    #[default]
+    Normal,
+    /// Acts once more after this turn.
+    ExtraTurn,
+    /// Loses the turn.
+    Frozen,
+}
+
+#[derive(
+    serde::Deserialize, serde::Serialize, Default, Clone, Copy, PartialEq, Eq, Hash, Debug,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum DamageType {
+    #[default]
+    Physical,
+    Fire,
+    Ice,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone)]
+#[serde(default)]
+pub struct Stats {
+    pub attack: i32,
+    pub min_dmg: i32,
+    pub max_dmg: i32,
+    pub defense: i32,
+    pub health: i32,
+    pub luck: i32,
+    pub leadership: i32,
+    /// Percent chance of an extra turn, or of losing the turn when negative.
+    pub morale: i32,
+    /// Hexes per move on the battlefield.
+    pub speed: i32,
+    pub abilities: Vec<Ability>,
+    pub damage_type: DamageType,
+    /// Percent of incoming damage of each type that is ignored, negative for vulnerabilities.
+    pub resistances: HashMap<DamageType, i32>,
+    /// Percent of spell damage ignored, also the chance to shrug off a debuff.
+    pub spell_resistance: i32,
+    /// Terrains that give `NATIVE_TERRAIN_BONUS`.
+    pub native_terrains: Vec<String>,
+    /// Extra stats on each named terrain, declared as `"terrain_bonuses": {"swamp": {"speed": -2}}`.
+    pub terrain_bonuses: HashMap<String, Stats>,
+    /// Teams mixing factions lose `Rules::mixed_faction_morale`, empty for none.
+    pub faction: String,
+    /// Encounter points per creature, 0 to derive them from `Stats::power`.
+    pub cost: i32,
+    pub desc: String,
+    /// Read only from saves made before shields, loading a `Unit` turns it into a shield.
+    #[serde(skip_serializing)]
+    pub absorb: i32,
+}
+
+impl Stats {
+    /// Adds the numeric stats, resistances and abilities of `other`,
+    /// `damage_type`, terrains, `faction` and `desc` are left alone.
+    pub fn add(&mut self, other: &Stats) {
+        self.attack += other.attack;
+        self.min_dmg += other.min_dmg;
+        self.max_dmg += other.max_dmg;
+        self.defense += other.defense;
+        self.health += other.health;
+        self.luck += other.luck;
+        self.leadership += other.leadership;
+        self.morale += other.morale;
+        self.speed += other.speed;
+        self.spell_resistance += other.spell_resistance;
+        self.abilities.extend(other.abilities.iter().cloned());
+        for (damage_type, resistance) in other.resistances.iter() {
+            *self.resistances.entry(*damage_type).or_default() += resistance;
+        }
+    }
+
+    /// Rough worth of one creature, the geometric mean of its damage and toughness.
+    /// Attack and defence count as they do against a foe with none, abilities as a flat bonus.
+    pub fn power(&self) -> f32 {
+        let mut offense = (self.min_dmg as f32 + self.max_dmg as f32).max(0.0) / 2.0
+            * (1.0 + (self.attack * 5).clamp(0, 300) as f32 / 100.0);
+        let mut toughness = self.effective_health();
+        for ability in self.abilities.iter() {
+            match ability {
+                Ability::DoubleStrike => offense *= 2.0,
+                Ability::Shooter => offense *= 1.5,
+                Ability::IgnoreDefense(percent) => offense *= 1.0 + *percent as f32 / 400.0,
+                Ability::NoEnemyRetaliation => toughness *= 1.25,
+                Ability::UnlimitedRetaliation => offense *= 1.25,
+                Ability::FirstStrike => toughness *= 1.1,
+                Ability::Hates(_) | Ability::Undead => {}
+            }
+        }
+        (offense * toughness).sqrt()
+    }
+
+    /// Health of one creature scaled by defence against a foe with no attack,
+    /// and by physical resistance, which is capped at 75%.
+    pub fn effective_health(&self) -> f32 {
+        self.health.max(0) as f32 * (1.0 + self.defense.max(0) as f32 * 2.5 / 100.0)
+            / (1.0 - self.resistance(DamageType::Physical).clamp(-100, 75) as f32 / 100.0)
+    }
+
+    pub fn resistance(&self, damage_type: DamageType) -> i32 {
+        self.resistances
+            .get(&damage_type)
+            .copied()
+            .unwrap_or(0)
+            .min(100)
+    }
+
+    /// Treats every field of `percent` as a percent change of the same stat.
+    pub fn scale(&mut self, percent: &Stats) {
+        let scale = |value: i32, percent: i32| value + value * percent / 100;
+        self.attack = scale(self.attack, percent.attack);
+        self.min_dmg = scale(self.min_dmg, percent.min_dmg);
+        self.max_dmg = scale(self.max_dmg, percent.max_dmg);
+        self.defense = scale(self.defense, percent.defense);
+        self.health = scale(self.health, percent.health);
+        self.luck = scale(self.luck, percent.luck);
+        self.leadership = scale(self.leadership, percent.leadership);
+        self.morale = scale(self.morale, percent.morale);
+        self.speed = scale(self.speed, percent.speed);
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SpellKind {
+    #[default]
+    Damage,
+    Heal,
+    /// Puts `Spell::effect` on an ally.
+    Buff,
+    /// Puts `Spell::effect` on an enemy, resisted with `Stats::spell_resistance` percent chance.
+    Debuff,
+}
+
+/// Declared in the spells json as
+/// `"Fireball": {"kind": "damage", "base": 10, "per_power": 10, "damage_type": "fire"}`.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone)]
+#[serde(default)]
+pub struct Spell {
+    pub kind: SpellKind,
+    /// Damage or healing, rounds for buffs and debuffs, at zero power.
+    pub base: i32,
+    /// Added to `base` for every point of power.
+    pub per_power: i32,
+    pub damage_type: DamageType,
+    /// Whether healing brings back dead creatures.
+    pub resurrect: bool,
+    /// Put on the target by buffs and debuffs, named after the spell when unnamed.
+    pub effect: StatusEffect,
+    #[serde(rename = "description")]
+    pub desc: String,
+}
+
+impl Spell {
+    pub fn amount(&self, power: i32) -> i32 {
+        self.base + self.per_power * power
+    }
+
+    /// Whether the spell is cast on the caster's own team.
+    pub fn friendly(&self) -> bool {
+        matches!(self.kind, SpellKind::Heal | SpellKind::Buff)
+    }
+}
+
+/// What a spell did to its target.
+pub enum Cast {
+    Damage(Strike),
+    /// Hp restored and creatures revived.
+    Heal(i32, i32),
+    /// Rounds the effect lasts.
+    Effect(i32),
+    Resisted,
+}
+
+/// Item carried by a hero.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone)]
+#[serde(default)]
+pub struct Artifact {
+    pub name: String,
+    pub stats: Stats,
+}
+
+/// Commander of a team, its stats and artifacts add to every unit of the team.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone)]
+#[serde(default)]
+pub struct Hero {
+    pub name: String,
+    pub stats: Stats,
+    pub artifacts: Vec<Artifact>,
+}
+
+impl Hero {
+    /// Stats of the hero with all artifacts added.
+    pub fn bonus(&self) -> Stats {
+        let mut bonus = Stats::default();
+        bonus.add(&self.stats);
+        for artifact in self.artifacts.iter() {
+            bonus.add(&artifact.stats);
+        }
+        bonus
+    }
+}
+
+/// What happens when an effect with the same name is already on the unit.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Stacking {
+    /// Keep the old effect and extend it to the longer duration.
+    #[default]
+    Refresh,
+    /// Drop the old effect.
+    Replace,
+    /// Keep both.
+    Stack,
+}
+
+/// Spell or aura that changes stats for a number of rounds.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone)]
+#[serde(default)]
+pub struct StatusEffect {
+    pub name: String,
+    pub delta: Stats,
+    /// Percent changes applied after all deltas, `attack: -20` means -20% attack.
+    pub percent: Stats,
+    pub rounds: i32,
+    pub stacking: Stacking,
+}
+
+/// Pool of hp that takes damage before the creatures do.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Shield {
+    /// Spell, artifact or ability the shield comes from.
+    pub source: String,
+    pub amount: i32,
+    /// Rounds left, `None` lasts until the shield is used up.
+    pub rounds: Option<i32>,
+    /// Only damage of this type is absorbed when set.
+    pub damage_type: Option<DamageType>,
+}
+
+impl Shield {
+    pub fn blocks(&self, damage_type: DamageType) -> bool {
+        self.damage_type.map_or(true, |t| t == damage_type)
+    }
+}
+
+/// Loaded through `Unit::migrate`, so fields added later get values that fit older saves.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone)]
+#[serde(default, remote = "Self")]
+pub struct Unit {
+    pub name: String,
+    pub stats: Stats,
+    pub value: i32,
+    pub damage_left: i32,
+    /// Stack size as set by the GM, the cap for resurrection.
+    pub initial: i32,
+    pub kills: i32,
+    pub losses: i32,
+    pub damage_dealt: i32,
+    pub damage_taken: i32,
+    /// Retaliations used in the current round.
+    pub retaliated: i32,
+    pub effects: Vec<StatusEffect>,
+    pub shields: Vec<Shield>,
+    pub position: Option<Hex>,
+    /// Whether the unit is done for the current round.
+    pub acted: bool,
+    /// Morale rolled at the first turn of the current round.
+    pub morale: Option<Morale>,
+}
+
+impl<'de> serde::Deserialize<'de> for Unit {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Unit::deserialize(deserializer).map(Unit::migrate)
+    }
+}
+
+impl serde::Serialize for Unit {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Unit::serialize(self, serializer)
+    }
+}
+
+impl Unit {
+    /// Fills in what saves from before casualty tracking lack,
+    /// and moves the absorb of saves from before shields into a shield.
+    fn migrate(mut self) -> Self {
+        if self.initial == 0 {
+            self.initial = self.value;
+        }
+        if self.stats.absorb > 0 {
+            self.shields.push(Shield {
+                source: "absorb".to_string(),
+                amount: self.stats.absorb,
+                ..Default::default()
+            });
+        }
+        self.stats.absorb = 0;
+        self
+    }
+
+    pub fn new_round(&mut self) {
+        for shield in self.shields.iter_mut() {
+            if let Some(rounds) = shield.rounds.as_mut() {
+                *rounds -= 1;
+            }
+        }
+        self.shields
+            .retain(|shield| shield.rounds.map_or(true, |rounds| rounds > 0));
+        self.retaliated = 0;
+        self.acted = false;
+        self.morale = None;
+        for effect in self.effects.iter_mut() {
+            effect.rounds -= 1;
+        }
+        self.effects.retain(|effect| effect.rounds > 0);
+    }
+
+    /// Forgets the casualties of the last battle and takes the current count as the new start.
+    pub fn reset_casualties(&mut self) {
+        self.initial = self.value;
+        self.kills = 0;
+        self.losses = 0;
+        self.damage_dealt = 0;
+        self.damage_taken = 0;
+    }
+
+    pub fn add_effect(&mut self, effect: StatusEffect) {
+        let same = self.effects.iter().position(|e| e.name == effect.name);
+        match (effect.stacking, same) {
+            (Stacking::Refresh, Some(i)) => {
+                self.effects[i].rounds = self.effects[i].rounds.max(effect.rounds);
+            }
+            (Stacking::Replace, Some(i)) => self.effects[i] = effect,
+            _ => self.effects.push(effect),
+        }
+    }
+
+    /// Ends the turn, unless morale granted another one.
+    pub fn end_turn(&mut self) {
+        if self.morale == Some(Morale::ExtraTurn) {
+            self.morale = Some(Morale::Normal);
+        } else {
+            self.acted = true;
+        }
+    }
+
+    /// Moves `count` undamaged creatures into a new stack with the same bonuses and effects.
+    /// Every shield is shared out by creature count, casualties stay with this stack.
+    pub fn split(&mut self, count: i32) -> Option<Unit> {
+        if count <= 0 || count >= self.value {
+            return None;
+        }
+        let mut shields = self.shields.clone();
+        for (shield, split) in self.shields.iter_mut().zip(shields.iter_mut()) {
+            split.amount = shield.amount * count / self.value;
+            shield.amount -= split.amount;
+        }
+        self.value -= count;
+        self.initial = (self.initial - count).max(self.value);
+        Some(Unit {
+            name: self.name.clone(),
+            stats: self.stats.clone(),
+            value: count,
+            initial: count,
+            retaliated: self.retaliated,
+            effects: self.effects.clone(),
+            shields,
+            ..Default::default()
+        })
+    }
+
+    /// Total that the shields can absorb, against `damage_type` when given.
+    pub fn shield(&self, damage_type: Option<DamageType>) -> i32 {
+        self.shields
+            .iter()
+            .filter(|shield| damage_type.map_or(true, |t| shield.blocks(t)))
+            .map(|shield| shield.amount)
+            .sum()
+    }
+
+    /// Drains the shields that block `damage_type`, returns what is left of `damage`.
+    /// Shields only against this damage type go first, then the ones that expire soonest,
+    /// then the oldest.
+    pub fn absorb(
+        &mut self,
+        damage: f32,
+        damage_type: DamageType,
+        breakdown: &mut Vec<String>,
+    ) -> f32 {
+        let mut order = (0..self.shields.len())
+            .filter(|i| self.shields[*i].blocks(damage_type))
+            .collect::<Vec<_>>();
+        order.sort_by_key(|i| {
+            let shield = &self.shields[*i];
+            (
+                shield.damage_type.is_none(),
+                shield.rounds.unwrap_or(i32::MAX),
+            )
+        });
+        let mut damage = damage;
+        for i in order {
+            if damage <= 0.0 {
+                break;
+            }
+            let shield = &mut self.shields[i];
+            let absorbed = (damage as i32).min(shield.amount);
+            if damage < shield.amount as f32 {
+                // the fraction is absorbed too, the shield loses whole points only
+                shield.amount -= damage as i32;
+                damage = 0.0;
+            } else {
+                damage -= shield.amount as f32;
+                shield.amount = 0;
+            }
+            if absorbed > 0 {
+                breakdown.push(format!("absorbed by {}: {}", shield.source, absorbed));
+            }
+        }
+        self.shields.retain(|shield| shield.amount > 0);
+        damage
+    }
+}
+
+/// Average of `a` and `b` weighted by their creature counts, rounded toward zero.
+fn weighted(a: i32, a_count: i32, b: i32, b_count: i32) -> i32 {
+    let total = a_count as i64 + b_count as i64;
+    if total == 0 {
+        return a;
+    }
+    ((a as i64 * a_count as i64 + b as i64 * b_count as i64) / total) as i32
+}
+
+/// Result of a single blow.
+#[derive(serde::Serialize, Default, Clone)]
+pub struct Strike {
+    pub damage: i32,
+    pub text: [String; 2],
+    pub retaliation: bool,
+    pub killed: i32,
+    /// How the damage was arrived at, one step per line.
+    pub breakdown: Vec<String>,
+}
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone)]
+#[serde(default)]
+pub struct Calc {
+    pub classes: HashMap<String, Stats>,
+    pub spells: HashMap<String, Spell>,
+    pub rules: Rules,
+    /// Terrain the battle is fought on.
+    pub terrain: Option<String>,
+    #[serde(skip)]
+    dice: Dice,
+    #[serde(skip)]
+    generation: Generation,
+}
+
+/// Source of every random roll, seeded from entropy unless `Calc::seed` is called.
+#[derive(Clone)]
+struct Dice(RefCell<StdRng>);
+
+impl Default for Dice {
+    fn default() -> Self {
+        Self(RefCell::new(StdRng::from_entropy()))
+    }
+}
+
+/// Number of the class library a `Calc` holds, new for every `Calc` and every `Calc::update`.
+#[derive(Clone, Copy)]
+struct Generation(u64);
+
+impl Default for Generation {
+    fn default() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, AtomicOrdering::Relaxed))
+    }
+}
+
+/// Outcome of repeating the same attack on fresh copies of the units.
+#[derive(serde::Serialize, Default)]
+pub struct Simulation {
+    pub runs: u32,
+    pub min_damage: i32,
+    pub max_damage: i32,
+    pub avg_damage: f32,
+    pub avg_killed: f32,
+    /// Damage and kills of the defender's retaliations.
+    pub avg_retaliation_damage: f32,
+    pub avg_retaliation_killed: f32,
+    /// Share of runs that wiped out the defender.
+    pub defender_wiped: f32,
+}
+
+/// Numbers for comparing classes, all per creature.
+#[derive(serde::Serialize, Default, Clone)]
+pub struct Rating {
+    pub effective_health: f32,
+    /// Average roll, before attack and defence.
+    pub avg_damage: f32,
+    /// Damage dealt to the reference class per point of own health.
+    pub damage_per_health: f32,
+    pub power: f32,
+    pub cost: i32,
+}
+
+fn map_json(json: &str) -> Option<(HashMap<String, Stats>, i32)> {
+    let mut result = HashMap::new();
+    let mut errs = 0;
+
+    if let Ok(value) = serde_json::from_str::<Value>(json) {
+        for (key, value) in value.as_object()? {
+            if let Some(stats) = deser_stats(value) {
+                result.insert(key.to_string(), stats);
+            } else {
+                errs += 1;
+            }
+        }
+
+        Some((result, errs))
+    } else {
+        None
+    }
+}
+
+pub fn deser_stats(value: &Value) -> Option<Stats> {
+    let desc = value["description"].as_str()?.to_string();
+    let (luck, leadership) = match (value["luck"].as_i64(), value["leadership"].as_i64()) {
+        (Some(v1), Some(v2)) => (v1 as i32, v2 as i32),
+        (None, None) => {
+            let (v1, v2) = Calc::parse_old_luck_and_leadership(&desc);
+            (v1.unwrap_or(0), v2.unwrap_or(0))
+        }
+        (Some(v1), None) => (
+            v1 as i32,
+            Calc::parse_old_luck_and_leadership(&desc).1.unwrap_or(0),
+        ),
+        (None, Some(v2)) => (
+            Calc::parse_old_luck_and_leadership(&desc).0.unwrap_or(0),
+            v2 as i32,
+        ),
+    };
+    Some(Stats {
+        attack: value["attack"].as_i64()? as i32,
+        min_dmg: value["min_dmg"].as_i64()? as i32,
+        max_dmg: value["max_dmg"].as_i64()? as i32,
+        defense: value["defence"].as_i64()? as i32,
+        health: value["health"].as_i64()? as i32,
+        luck,
+        leadership,
+        speed: deser_optional(value, "speed")?,
+        abilities: deser_optional(value, "abilities")?,
+        damage_type: deser_optional(value, "damage_type")?,
+        resistances: deser_optional(value, "resistances")?,
+        spell_resistance: deser_optional(value, "spell_resistance")?,
+        morale: deser_optional(value, "morale")?,
+        native_terrains: deser_optional(value, "native_terrains")?,
+        terrain_bonuses: deser_optional(value, "terrain_bonuses")?,
+        faction: deser_optional(value, "faction")?,
+        cost: deser_optional(value, "cost")?,
+        desc,
+        absorb: 0,
+    })
+}
+
+/// Missing keys give the default, malformed ones give `None`.
+fn deser_optional<T: DeserializeOwned + Default>(value: &Value, key: &str) -> Option<T> {
+    match value.get(key) {
+        Some(field) => serde_json::from_value(field.clone()).ok(),
+        None => Some(T::default()),
+    }
+}
+
+impl Calc {
+    /// Makes every following roll repeat for the same `seed`.
+    pub fn seed(&mut self, seed: u64) {
+        self.dice = Dice(RefCell::new(StdRng::seed_from_u64(seed)));
+    }
+
+    pub(crate) fn rng(&self) -> RefMut<'_, StdRng> {
+        self.dice.0.borrow_mut()
+    }
+
+    pub fn update(&mut self, json: &str) -> i32 {
+        if let Some((new_classes, errs)) = map_json(json) {
+            self.classes = new_classes;
+            self.generation = Generation::default();
+            errs
+        } else {
+            -1
+        }
+    }
+
+    /// Changes whenever other classes are loaded, a cheap check for caches of class data.
+    /// Unlike `library_hash` it differs between two `Calc`s with the same classes.
+    pub fn generation(&self) -> u64 {
+        self.generation.0
+    }
+
+    /// Fingerprint of the class library, equal on every machine that loaded the same classes.
+    pub fn library_hash(&self) -> u64 {
+        // Going through `Value` sorts every map by key.
+        let json = serde_json::to_value(&self.classes)
+            .map(|value| value.to_string())
+            .unwrap_or_default();
+        // FNV-1a, unlike `DefaultHasher` it is fixed across releases.
+        json.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    /// Encounter points of one creature of `class`, declared or derived from its stats.
+    pub fn cost(&self, class: &str) -> Option<i32> {
+        let stats = self.classes.get(class)?;
+        if stats.cost > 0 {
+            return Some(stats.cost);
+        }
+        Some((stats.power().round() as i32).max(1))
+    }
+
+    /// Same as `update` for the spells json.
+    pub fn update_spells(&mut self, json: &str) -> i32 {
+        let Ok(Value::Object(value)) = serde_json::from_str::<Value>(json) else {
+            return -1;
+        };
+        let mut errs = 0;
+        self.spells.clear();
+        for (key, value) in value {
+            match serde_json::from_value(value) {
+                Ok(spell) => {
+                    self.spells.insert(key, spell);
+                }
+                Err(_) => errs += 1,
+            }
+        }
+        errs
+    }
+
+    pub fn parse_old_luck_and_leadership(desc: &str) -> (Option<i32>, Option<i32>) {
+        (
+            if let Some(luck_start) = desc.find("Удача:") {
+                let luck_start = luck_start + "Удача:".len();
+                if let Some(luck_end) = desc[luck_start..].find(',') {
+                    if let Ok(v) = desc[luck_start..luck_start + luck_end]
+                        .trim()
+                        .parse::<i32>()
+                    {
+                        Some(v)
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            } else {
+                None
+            },
+            if let Some(leadership_start) = desc.find("Лидерство:") {
+                let leadership_start = leadership_start + "Лидерство:".len();
+                if let Some(leadership_end) = desc[leadership_start..].find(',') {
+                    if let Ok(v) = desc[leadership_start..leadership_start + leadership_end]
+                        .trim()
+                        .parse::<i32>()
+                    {
+                        Some(v)
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            } else {
+                None
+            },
+        )
+    }
+
+    /// Class abilities followed by the ones granted to this unit and by its effects.
+    pub fn abilities<'a>(&'a self, unit: &'a Unit) -> impl Iterator<Item = &'a Ability> {
+        self.classes
+            .get(&unit.name)
+            .into_iter()
+            .flat_map(|stats| stats.abilities.iter())
+            .chain(unit.stats.abilities.iter())
+            .chain(unit.effects.iter().flat_map(|e| e.delta.abilities.iter()))
+    }
+
+    /// Every terrain mentioned in the class data, sorted.
+    pub fn terrains(&self) -> Vec<String> {
+        let mut terrains = self
+            .classes
+            .values()
+            .flat_map(|class| {
+                class
+                    .native_terrains
+                    .iter()
+                    .chain(class.terrain_bonuses.keys())
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        terrains.sort();
+        terrains.dedup();
+        terrains
+    }
+
+    /// What the current terrain gives to units of `class`.
+    pub fn terrain_bonus(&self, class: &Stats) -> Stats {
+        let Some(terrain) = &self.terrain else {
+            return Stats::default();
+        };
+        let mut bonus = class
+            .terrain_bonuses
+            .get(terrain)
+            .cloned()
+            .unwrap_or_default();
+        if class.native_terrains.contains(terrain) {
+            bonus.attack += NATIVE_TERRAIN_BONUS;
+            bonus.defense += NATIVE_TERRAIN_BONUS;
+            bonus.speed += NATIVE_TERRAIN_BONUS;
+        }
+        bonus
+    }
+
+    /// Everything that adds to the stats of `unit`, labeled by where it comes from:
+    /// the class itself, the unit bonuses, the hero, the terrain and each status effect.
+    pub fn stat_sources(&self, unit: &Unit, hero: &Stats) -> Option<Vec<(String, Stats)>> {
+        let class = self.classes.get(&unit.name)?;
+        let mut sources = vec![
+            ("class".to_string(), class.clone()),
+            ("unit".to_string(), unit.stats.clone()),
+            ("hero".to_string(), hero.clone()),
+            ("terrain".to_string(), self.terrain_bonus(class)),
+        ];
+        for effect in unit.effects.iter() {
+            sources.push((effect.name.clone(), effect.delta.clone()));
+        }
+        Some(sources)
+    }
+
+    /// Sum of `stat_sources` scaled by the percent changes of status effects, as used by `calculate`.
+    pub fn effective_stats(&self, unit: &Unit, hero: &Stats) -> Option<Stats> {
+        let mut sources = self.stat_sources(unit, hero)?.into_iter();
+        let (_, mut stats) = sources.next()?;
+        for (_, delta) in sources {
+            stats.add(&delta);
+        }
+        let mut percent = Stats::default();
+        for effect in unit.effects.iter() {
+            percent.add(&effect.percent);
+        }
+        stats.scale(&percent);
+        Some(stats)
+    }
+
+    /// Morale of `unit` with the penalties for the rest of its `team`, undead have none.
+    pub fn morale(&self, unit: &Unit, hero: &Stats, team: &[Option<Unit>]) -> i32 {
+        if self.has_ability(unit, &Ability::Undead) {
+            return 0;
+        }
+        let Some(stats) = self.effective_stats(unit, hero) else {
+            return 0;
+        };
+        let alive = team.iter().flatten().filter(|unit| unit.value > 0);
+        let factions = alive
+            .clone()
+            .filter_map(|unit| self.classes.get(&unit.name))
+            .map(|class| &class.faction)
+            .filter(|faction| !faction.is_empty())
+            .collect::<HashSet<_>>();
+        let mut morale =
+            stats.morale - (factions.len() as i32 - 1).max(0) * self.rules.mixed_faction_morale;
+        if alive
+            .clone()
+            .any(|unit| self.has_ability(unit, &Ability::Undead))
+        {
+            morale -= self.rules.undead_morale;
+        }
+        morale
+    }
+
+    pub fn roll_morale(&self, morale: i32) -> Morale {
+        let roll = self.rng().gen_range(0..100);
+        if roll < morale {
+            Morale::ExtraTurn
+        } else if roll < -morale {
+            Morale::Frozen
+        } else {
+            Morale::Normal
+        }
+    }
+
+    /// Rolls `morale` on the first turn of the round, a frozen unit is done at once.
+    /// The extra turn granted by morale is not rolled again and gives `Morale::Normal`.
+    pub fn start_turn(&self, unit: &mut Unit, morale: i32) -> Morale {
+        if unit.morale.is_some() {
+            return Morale::Normal;
+        }
+        let roll = self.roll_morale(morale);
+        unit.morale = Some(roll);
+        if roll == Morale::Frozen {
+            unit.acted = true;
+        }
+        roll
+    }
+
+    /// Units of all `teams` still to act this round as `(team, slot)`,
+    /// fastest first, ties by team and slot.
+    pub fn turn_order(&self, teams: &[(&[Option<Unit>], &Stats)]) -> Vec<(usize, usize)> {
+        let mut order = Vec::new();
+        for (team_i, (units, hero)) in teams.iter().enumerate() {
+            for (slot, unit) in units.iter().enumerate() {
+                let Some(unit) = unit else { continue };
+                if unit.value <= 0 || unit.acted {
+                    continue;
+                }
+                if let Some(stats) = self.effective_stats(unit, hero) {
+                    order.push((stats.speed, team_i, slot));
+                }
+            }
+        }
+        order.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+        order
+            .into_iter()
+            .map(|(_, team, slot)| (team, slot))
+            .collect()
+    }
+
+    pub fn has_ability(&self, unit: &Unit, ability: &Ability) -> bool {
+        self.abilities(unit).any(|a| a == ability)
+    }
+
+    pub fn is_ranged(&self, attacker: &Unit, attack: &Attack) -> bool {
+        attack.mode == AttackMode::Ranged && self.has_ability(attacker, &Ability::Shooter)
+    }
+
+    /// Whether `defender` would strike back if `attacker` attacked it right now.
+    pub fn will_retaliate(&self, defender: &Unit, attacker: &Unit, attack: &Attack) -> bool {
+        attack.retaliation
+            && !self.is_ranged(attacker, attack)
+            && defender.value > 0
+            && !self.has_ability(attacker, &Ability::NoEnemyRetaliation)
+            && (defender.retaliated < RETALIATIONS_PER_ROUND
+                || self.has_ability(defender, &Ability::UnlimitedRetaliation))
+    }
+
+    /// Restores up to `amount` hp to `unit` and returns how much was restored.
+    /// The top creature is healed first, then with `resurrect` dead creatures
+    /// are brought back, never above `Unit::initial`.
+    pub fn heal(&self, unit: &mut Unit, hero: &Stats, amount: i32, resurrect: bool) -> i32 {
+        let Some(health) = self.effective_stats(unit, hero).map(|stats| stats.health) else {
+            return 0;
+        };
+        if health <= 0 || (unit.value <= 0 && !resurrect) {
+            return 0;
+        }
+        let mut amount = amount.max(0);
+        let healed = amount.min(unit.damage_left);
+        unit.damage_left -= healed;
+        amount -= healed;
+        if !resurrect {
+            return healed;
+        }
+
+        let dead = unit.initial.max(unit.value) - unit.value.max(0);
+        let revived = (amount / health).min(dead);
+        unit.value = unit.value.max(0) + revived;
+        amount -= revived * health;
+        if amount > 0 && revived < dead {
+            unit.value += 1;
+            unit.damage_left = health - amount;
+            return healed + revived * health + amount;
+        }
+        healed + revived * health
+    }
+
+    /// Folds `from` into `into`, both stacks must be of the same class.
+    /// Per-unit stat bonuses and resistances become the average weighted by creature count,
+    /// abilities and effects missing from `into` are copied over, shields and casualties add up.
+    /// Wounds of both stacks are kept, a creature dies whenever they add up to its health.
+    pub fn merge(&self, into: &mut Unit, from: &Unit, hero: &Stats) -> Result<(), &'static str> {
+        if into.name != from.name {
+            return Err("different classes");
+        }
+        let (a, b) = (into.value.max(0), from.value.max(0));
+        let (to, other) = (&mut into.stats, &from.stats);
+        to.attack = weighted(to.attack, a, other.attack, b);
+        to.min_dmg = weighted(to.min_dmg, a, other.min_dmg, b);
+        to.max_dmg = weighted(to.max_dmg, a, other.max_dmg, b);
+        to.defense = weighted(to.defense, a, other.defense, b);
+        to.health = weighted(to.health, a, other.health, b);
+        to.luck = weighted(to.luck, a, other.luck, b);
+        to.leadership = weighted(to.leadership, a, other.leadership, b);
+        to.morale = weighted(to.morale, a, other.morale, b);
+        to.speed = weighted(to.speed, a, other.speed, b);
+        to.spell_resistance = weighted(to.spell_resistance, a, other.spell_resistance, b);
+        for damage_type in other.resistances.keys() {
+            to.resistances.entry(*damage_type).or_default();
+        }
+        for (damage_type, resistance) in to.resistances.iter_mut() {
+            *resistance = weighted(*resistance, a, other.resistance(*damage_type), b);
+        }
+        for ability in other.abilities.iter() {
+            if !to.abilities.contains(ability) {
+                to.abilities.push(ability.clone());
+            }
+        }
+        for effect in from.effects.iter() {
+            if !into.effects.iter().any(|e| e.name == effect.name) {
+                into.effects.push(effect.clone());
+            }
+        }
+        into.shields.extend(from.shields.iter().cloned());
+
+        let damage =
+            if a > 0 { into.damage_left } else { 0 } + if b > 0 { from.damage_left } else { 0 };
+        into.value = a + b;
+        into.damage_left = damage;
+        into.initial += from.initial;
+        into.kills += from.kills;
+        into.losses += from.losses;
+        into.damage_dealt += from.damage_dealt;
+        into.damage_taken += from.damage_taken;
+        into.retaliated = into.retaliated.max(from.retaliated);
+        if let Some(health) = self.effective_stats(into, hero).map(|stats| stats.health) {
+            while health > 0 && into.damage_left >= health && into.value > 0 {
+                into.value -= 1;
+                into.damage_left -= health;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `calculate` `runs` times, every time on fresh copies of the units.
+    pub fn simulate(
+        &self,
+        defender: &Unit,
+        attacker: &Unit,
+        attack: &Attack,
+        runs: u32,
+    ) -> Simulation {
+        let mut result = Simulation {
+            runs,
+            min_damage: i32::MAX,
+            ..Default::default()
+        };
+        for _ in 0..runs {
+            let (mut defender, mut attacker) = (defender.clone(), attacker.clone());
+            let strikes = self.calculate(&mut defender, &mut attacker, attack);
+            let (mut damage, mut killed) = (0i32, 0);
+            for strike in strikes.iter() {
+                if strike.retaliation {
+                    result.avg_retaliation_damage += strike.damage as f32;
+                    result.avg_retaliation_killed += strike.killed as f32;
+                } else {
+                    damage = damage.saturating_add(strike.damage);
+                    killed += strike.killed;
+                }
+            }
+            result.min_damage = result.min_damage.min(damage);
+            result.max_damage = result.max_damage.max(damage);
+            result.avg_damage += damage as f32;
+            result.avg_killed += killed as f32;
+            if defender.value <= 0 {
+                result.defender_wiped += 1.0;
+            }
+        }
+        if runs == 0 {
+            result.min_damage = 0;
+            return result;
+        }
+        let runs = runs as f32;
+        result.avg_damage /= runs;
+        result.avg_killed /= runs;
+        result.avg_retaliation_damage /= runs;
+        result.avg_retaliation_killed /= runs;
+        result.defender_wiped /= runs;
+        result
+    }
+
+    /// Rates `class` on its own and attacking `reference` with `runs` simulated attacks,
+    /// damage against a missing reference class is 0.
+    pub fn rating(&self, class: &str, reference: &str, runs: u32) -> Option<Rating> {
+        let stats = self.classes.get(class)?;
+        // A big stack evens out the rounding of every blow.
+        let attacker = Unit {
+            name: class.to_string(),
+            value: 100,
+            ..Default::default()
+        };
+        let damage = match self.classes.get(reference) {
+            Some(reference_stats) => {
+                // Enough creatures to outlast every bonus a blow can get.
+                let needed = 100 * 16 * i64::from(stats.max_dmg.max(1))
+                    / i64::from(reference_stats.health.max(1))
+                    + 1;
+                let defender = Unit {
+                    name: reference.to_string(),
+                    value: needed.min(i32::MAX.into()) as i32,
+                    ..Default::default()
+                };
+                let attack = Attack {
+                    mode: AttackMode::Ranged,
+                    ..Default::default()
+                };
+                self.simulate(&defender, &attacker, &attack, runs)
+                    .avg_damage
+                    / 100.0
+            }
+            None => 0.0,
+        };
+        Some(Rating {
+            effective_health: stats.effective_health(),
+            avg_damage: (stats.min_dmg as f32 + stats.max_dmg as f32) / 2.0,
+            damage_per_health: damage / stats.health.max(1) as f32,
+            power: stats.power(),
+            cost: self.cost(class)?,
+        })
+    }
+
+    /// Resolves a whole attack and returns every blow in the order it happened.
+    pub fn calculate(
+        &self,
+        defender: &mut Unit,
+        attacker: &mut Unit,
+        attack: &Attack,
+    ) -> Vec<Strike> {
+        let mut strikes = Vec::new();
+        let first_strike = self.has_ability(defender, &Ability::FirstStrike)
+            && self.will_retaliate(defender, attacker, attack);
+        if first_strike {
+            defender.retaliated += 1;
+            strikes.push(self.retaliate(attacker, defender, attack));
+        }
+        if attacker.value > 0 {
+            strikes.push(self.strike(defender, attacker, attack, None));
+        }
+        if !first_strike && self.will_retaliate(defender, attacker, attack) {
+            defender.retaliated += 1;
+            strikes.push(self.retaliate(attacker, defender, attack));
+        }
+        if self.has_ability(attacker, &Ability::DoubleStrike)
+            && attacker.value > 0
+            && defender.value > 0
+        {
+            strikes.push(self.strike(defender, attacker, attack, None));
+        }
+        strikes
+    }
+
+    /// Hits every target with one attack, area attacks are never retaliated.
+    /// Each target comes with its own `Attack`, as penalties and heroes differ between them.
+    /// With `roll_once` all targets take the same damage roll.
+    pub fn calculate_area(
+        &self,
+        defenders: &mut [(&mut Unit, Attack)],
+        attacker: &mut Unit,
+        roll_once: bool,
+    ) -> Vec<Strike> {
+        let roll = defenders
+            .first()
+            .filter(|_| roll_once)
+            .and_then(|(_, attack)| self.effective_stats(attacker, &attack.attacker_hero))
+            .map(|stats| self.roll(&stats));
+        let mut strikes = Vec::new();
+        if attacker.value <= 0 {
+            return strikes;
+        }
+        for (defender, attack) in defenders.iter_mut() {
+            strikes.push(self.strike(defender, attacker, attack, roll));
+        }
+        strikes
+    }
+
+    fn roll(&self, stats: &Stats) -> i32 {
+        self.rng()
+            .gen_range(stats.min_dmg..=stats.max_dmg.max(stats.min_dmg))
+    }
+
+    fn retaliate(&self, attacker: &mut Unit, defender: &mut Unit, attack: &Attack) -> Strike {
+        let retaliation = Attack {
+            attacker_hero: attack.defender_hero.clone(),
+            defender_hero: attack.attacker_hero.clone(),
+            ..Default::default()
+        };
+        Strike {
+            retaliation: true,
+            ..self.strike(attacker, defender, &retaliation, None)
+        }
+    }
+
+    /// `roll` is the per-creature damage, rolled here when `None`.
+    fn strike(
+        &self,
+        defender: &mut Unit,
+        attacker: &mut Unit,
+        attack_params: &Attack,
+        roll: Option<i32>,
+    ) -> Strike {
+        let mut strings: [String; 2] = [String::new(), String::new()];
+        let (Some(astats), Some(estats)) = (
+            self.effective_stats(attacker, &attack_params.attacker_hero),
+            self.effective_stats(defender, &attack_params.defender_hero),
+        ) else {
+            return Strike::default();
+        };
+        let attacker_luck = astats.luck;
+        let attacker_leadership = astats.leadership;
+        let attack = astats.attack;
+        let ignored: i32 = self
+            .abilities(attacker)
+            .map(|ability| match ability {
+                Ability::IgnoreDefense(percent) => *percent,
+                _ => 0,
+            })
+            .sum();
+        let defence = estats.defense;
+        let defence = defence - defence * ignored.min(100) / 100;
+
+        let luck = self.rng().gen_range(0..100);
+        if luck < attacker_luck {
+            strings[0] = "Ооо повезло-повезло!".to_string();
+        }
+
+        let leadership = self.rng().gen_range(0..100);
+        if leadership < attacker_leadership {
+            strings[1] = "Ебаны рот погнали!".to_string();
+        }
+
+        let damage = roll.unwrap_or_else(|| self.roll(&astats));
+        let health = estats.health;
+
+        let modifier = match attack.cmp(&defence) {
+            Ordering::Greater => 1.0 + ((attack - defence) * 5).min(300) as f32 / 100.0,
+            Ordering::Less => 1.0 - ((defence - attack) as f32 * 2.5).min(70.0) / 100.0,
+            Ordering::Equal => 1.0,
+        };
+        let hate = if self
+            .abilities(attacker)
+            .any(|ability| matches!(ability, Ability::Hates(class) if *class == defender.name))
+        {
+            1.0 + HATE_BONUS as f32 / 100.0
+        } else {
+            1.0
+        };
+
+        let resistance = estats.resistance(astats.damage_type);
+        let percent = attack_params.percent;
+        let ranged = self.is_ranged(attacker, attack_params);
+        let mut penalties = Vec::new();
+        if ranged && attack_params.long_range {
+            penalties.push(("long range", self.rules.long_range_penalty));
+        }
+        if ranged && attack_params.obstacle {
+            penalties.push(("obstacle", self.rules.obstacle_penalty));
+        }
+        if !ranged && self.has_ability(attacker, &Ability::Shooter) {
+            penalties.push(("shooter in melee", self.rules.melee_penalty));
+        }
+        let penalty: f32 = penalties
+            .iter()
+            .map(|(_, penalty)| 1.0 - *penalty as f32 / 100.0)
+            .product();
+
+        let mut breakdown = vec![format!("{} x {}", attacker.value, damage)];
+        if modifier != 1.0 {
+            breakdown.push(format!(
+                "atk {} vs def {}: {:+.0}%",
+                attack,
+                defence,
+                (modifier - 1.0) * 100.0
+            ));
+        }
+        if hate != 1.0 {
+            breakdown.push(format!("hate: +{}%", HATE_BONUS));
+        }
+        if percent != 100 {
+            breakdown.push(format!("{}%", percent));
+        }
+        for (name, penalty) in penalties.iter() {
+            breakdown.push(format!("{}: -{}%", name, penalty));
+        }
+        if resistance != 0 {
+            breakdown.push(format!(
+                "{:?} resistance: {}%",
+                astats.damage_type, resistance
+            ));
+        }
+
+        // Not in i32, a big stack times its damage does not fit one.
+        let damage_dealt = (damage as f64 * attacker.value as f64) as f32
+            * modifier
+            * hate
+            * (percent as f32 / 100.0)
+            * penalty
+            * (1.0 - resistance as f32 / 100.0);
+
+        let (damage, killed) = take_damage(
+            defender,
+            damage_dealt,
+            astats.damage_type,
+            health,
+            &mut breakdown,
+        );
+        attacker.kills = attacker.kills.saturating_add(killed);
+        attacker.damage_dealt = attacker.damage_dealt.saturating_add(damage);
+
+        Strike {
+            damage,
+            text: strings,
+            killed,
+            breakdown,
+            ..Default::default()
+        }
+    }
+
+    /// Applies `spell` cast with `power` to `target`, whose hero bonus is `hero`.
+    pub fn cast(
+        &self,
+        spell: &Spell,
+        name: &str,
+        power: i32,
+        target: &mut Unit,
+        hero: &Stats,
+    ) -> Cast {
+        let Some(stats) = self.effective_stats(target, hero) else {
+            return Cast::Resisted;
+        };
+        let amount = spell.amount(power);
+        let spell_resistance = stats.spell_resistance.min(100);
+        match spell.kind {
+            SpellKind::Damage => {
+                let resistance = stats.resistance(spell.damage_type);
+                let mut breakdown = vec![format!("{} power {}: {}", name, power, amount)];
+                if resistance != 0 {
+                    breakdown.push(format!(
+                        "{:?} resistance: {}%",
+                        spell.damage_type, resistance
+                    ));
+                }
+                if spell_resistance != 0 {
+                    breakdown.push(format!("spell resistance: {}%", spell_resistance));
+                }
+                let damage = amount as f32
+                    * (1.0 - resistance as f32 / 100.0)
+                    * (1.0 - spell_resistance as f32 / 100.0);
+                let (damage, killed) = take_damage(
+                    target,
+                    damage,
+                    spell.damage_type,
+                    stats.health,
+                    &mut breakdown,
+                );
+                Cast::Damage(Strike {
+                    damage,
+                    killed,
+                    breakdown,
+                    ..Default::default()
+                })
+            }
+            SpellKind::Heal => {
+                let value = target.value;
+                let healed = self.heal(target, hero, amount, spell.resurrect);
+                Cast::Heal(healed, target.value - value)
+            }
+            SpellKind::Debuff if self.rng().gen_range(0..100) < spell_resistance => Cast::Resisted,
+            SpellKind::Buff | SpellKind::Debuff => {
+                let mut effect = spell.effect.clone();
+                if effect.name.is_empty() {
+                    effect.name = name.to_string();
+                }
+                effect.rounds = amount.max(1);
+                target.add_effect(effect);
+                Cast::Effect(amount.max(1))
+            }
+        }
+    }
+}
+
+/// Takes `damage` off the shields of `defender`, then off its creatures of `health` hp each.
+/// Returns the damage that got through and the number of creatures killed.
+fn take_damage(
+    defender: &mut Unit,
+    damage: f32,
+    damage_type: DamageType,
+    health: i32,
+    breakdown: &mut Vec<String>,
+) -> (i32, i32) {
+    let damage = f64::from(defender.absorb(damage, damage_type, breakdown));
+    if damage <= 0.0 {
+        return (0, 0);
+    }
+
+    let value = defender.value;
+    // In f64, the health of a big stack does not fit an i32.
+    let all_health = defender.value as f64 * health as f64;
+
+    let creatures_left = (all_health - damage - defender.damage_left as f64) / health as f64;
+
+    if creatures_left <= 0.0 {
+        defender.value = 0;
+        defender.damage_left = 0;
+    } else {
+        defender.value = creatures_left.ceil() as i32;
+        defender.damage_left = ((creatures_left.ceil() - creatures_left) * health as f64) as i32;
+    }
+
+    // Casts saturate, damage past i32::MAX is reported as i32::MAX.
+    let (damage, killed) = (damage as i32, value - defender.value);
+    defender.losses = defender.losses.saturating_add(killed);
+    defender.damage_taken = defender.damage_taken.saturating_add(damage);
+    (damage, killed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc() -> Calc {
+        let mut calc = Calc::default();
+        let errs = calc.update(
+            r#"{
+                "Peasant": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": ""},
+                "Griffin": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": "",
+                            "abilities": ["unlimited_retaliation"]},
+                "Hydra": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": "",
+                          "abilities": ["no_enemy_retaliation"]},
+                "Crusader": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": "",
+                             "abilities": ["double_strike"]},
+                "Pikeman": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": "",
+                            "abilities": ["first_strike"]},
+                "Behemoth": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": "",
+                             "abilities": [{"ignore_defense": 40}]},
+                "Angel": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": "",
+                          "abilities": [{"hates": "Peasant"}]}
+            }"#,
+        );
+        assert_eq!(errs, 0);
+        calc
+    }
+
+    fn unit(name: &str, value: i32) -> Unit {
+        Unit {
+            name: name.to_string(),
+            value,
+            ..Default::default()
+        }
+    }
+
+    fn melee(percent: i32, retaliation: bool) -> Attack {
+        Attack {
+            percent,
+            retaliation,
+            ..Default::default()
+        }
+    }
+
+    fn damage(strikes: &[Strike]) -> Vec<(i32, bool)> {
+        strikes.iter().map(|s| (s.damage, s.retaliation)).collect()
+    }
+
+    #[test]
+    fn abilities_from_json() {
+        let calc = calc();
+        assert_eq!(calc.classes["Peasant"].abilities, vec![]);
+        assert_eq!(
+            calc.classes["Behemoth"].abilities,
+            vec![Ability::IgnoreDefense(40)]
+        );
+        assert_eq!(
+            calc.classes["Angel"].abilities,
+            vec![Ability::Hates("Peasant".to_string())]
+        );
+
+        let mut calc = Calc::default();
+        let errs = calc.update(
+            r#"{"Imp": {"attack": 1, "defence": 1, "min_dmg": 1, "max_dmg": 1, "health": 1, "description": "",
+                        "abilities": ["flies"]}}"#,
+        );
+        assert_eq!(errs, 1);
+    }
+
+    #[test]
+    fn retaliation_once_per_round() {
+        let calc = calc();
+        let mut attacker = unit("Peasant", 10);
+        let mut defender = unit("Peasant", 100);
+
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, true));
+        assert_eq!(damage(&strikes), vec![(10, false), (99, true)]);
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, true));
+        assert_eq!(damage(&strikes), vec![(1, false)]);
+
+        defender.new_round();
+        assert!(calc.will_retaliate(&defender, &attacker, &melee(100, true)));
+        assert!(!calc.will_retaliate(&defender, &attacker, &melee(100, false)));
+    }
+
+    #[test]
+    fn unlimited_retaliation() {
+        let calc = calc();
+        let mut attacker = unit("Peasant", 100);
+        let mut defender = unit("Griffin", 10);
+
+        for _ in 0..3 {
+            let strikes = calc.calculate(&mut defender, &mut attacker, &melee(0, true));
+            assert_eq!(damage(&strikes), vec![(0, false), (10, true)]);
+        }
+    }
+
+    #[test]
+    fn no_enemy_retaliation() {
+        let calc = calc();
+        let mut attacker = unit("Hydra", 10);
+        let mut defender = unit("Peasant", 10);
+
+        assert!(!calc.will_retaliate(&defender, &attacker, &melee(100, true)));
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, true));
+        assert_eq!(damage(&strikes), vec![(10, false)]);
+        assert_eq!(defender.retaliated, 0);
+    }
+
+    #[test]
+    fn double_strike() {
+        let calc = calc();
+        let mut attacker = unit("Crusader", 10);
+        let mut defender = unit("Peasant", 100);
+
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, true));
+        assert_eq!(damage(&strikes), vec![(10, false), (99, true), (1, false)]);
+        assert_eq!(defender.value, 99);
+    }
+
+    #[test]
+    fn first_strike() {
+        let calc = calc();
+        let mut attacker = unit("Peasant", 10);
+        let mut defender = unit("Pikeman", 100);
+
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, true));
+        assert_eq!(damage(&strikes), vec![(100, true)]);
+        assert_eq!(attacker.value, 0);
+
+        let mut attacker = unit("Peasant", 20);
+        defender.new_round();
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, true));
+        assert_eq!(damage(&strikes), vec![(100, true), (10, false)]);
+    }
+
+    #[test]
+    fn ignore_defense() {
+        let calc = calc();
+        let mut attacker = unit("Behemoth", 100);
+        let mut defender = unit("Peasant", 100);
+
+        // defence 10 -> 6, so attack is 4 points ahead for +20%
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, false));
+        assert_eq!(damage(&strikes), vec![(120, false)]);
+    }
+
+    #[test]
+    fn hates() {
+        let calc = calc();
+        let mut attacker = unit("Angel", 100);
+
+        let mut defender = unit("Peasant", 100);
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, false));
+        assert_eq!(damage(&strikes), vec![(150, false)]);
+
+        let mut defender = unit("Griffin", 100);
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, false));
+        assert_eq!(damage(&strikes), vec![(100, false)]);
+    }
+
+    #[test]
+    fn unit_granted_ability() {
+        let calc = calc();
+        let mut attacker = unit("Peasant", 10);
+        attacker.stats.abilities.push(Ability::DoubleStrike);
+        let mut defender = unit("Peasant", 100);
+
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, false));
+        assert_eq!(damage(&strikes), vec![(10, false), (10, false)]);
+    }
+
+    fn effect(name: &str, attack: i32, rounds: i32, stacking: Stacking) -> StatusEffect {
+        StatusEffect {
+            name: name.to_string(),
+            delta: Stats {
+                attack,
+                ..Default::default()
+            },
+            rounds,
+            stacking,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn effects_fold_into_stats() {
+        let calc = calc();
+        let mut attacker = unit("Peasant", 100);
+        attacker.add_effect(effect("bless", 10, 2, Stacking::Refresh));
+        attacker.add_effect(StatusEffect {
+            name: "rage".to_string(),
+            percent: Stats {
+                attack: 50,
+                ..Default::default()
+            },
+            rounds: 1,
+            ..Default::default()
+        });
+        assert_eq!(
+            calc.effective_stats(&attacker, &Stats::default())
+                .unwrap()
+                .attack,
+            30
+        );
+
+        // attack 30 vs defence 10 gives +100%
+        let mut defender = unit("Peasant", 100);
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(100, false));
+        assert_eq!(damage(&strikes), vec![(200, false)]);
+    }
+
+    #[test]
+    fn effects_stacking_and_expiry() {
+        let mut unit = unit("Peasant", 1);
+        unit.add_effect(effect("bless", 1, 1, Stacking::Refresh));
+        unit.add_effect(effect("bless", 5, 3, Stacking::Refresh));
+        assert_eq!(unit.effects.len(), 1);
+        assert_eq!(
+            (unit.effects[0].delta.attack, unit.effects[0].rounds),
+            (1, 3)
+        );
+
+        unit.add_effect(effect("bless", 5, 2, Stacking::Replace));
+        assert_eq!(unit.effects.len(), 1);
+        assert_eq!(
+            (unit.effects[0].delta.attack, unit.effects[0].rounds),
+            (5, 2)
+        );
+
+        unit.add_effect(effect("bless", 5, 1, Stacking::Stack));
+        assert_eq!(unit.effects.len(), 2);
+
+        unit.new_round();
+        assert_eq!(unit.effects.len(), 1);
+        unit.new_round();
+        assert!(unit.effects.is_empty());
+    }
+
+    #[test]
+    fn damage_types_and_resistances() {
+        let mut calc = Calc::default();
+        let errs = calc.update(
+            r#"{
+                "Peasant": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": ""},
+                "Phoenix": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": "",
+                            "damage_type": "fire", "resistances": {"fire": 100, "ice": -50}},
+                "Dragon": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": "",
+                           "damage_type": "ice"},
+                "Broken": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": "",
+                           "damage_type": "acid"}
+            }"#,
+        );
+        assert_eq!(errs, 1);
+        assert_eq!(calc.classes["Peasant"].damage_type, DamageType::Physical);
+
+        let mut phoenix = unit("Phoenix", 100);
+        let strikes = calc.calculate(&mut phoenix, &mut unit("Peasant", 100), &melee(100, false));
+        assert_eq!(damage(&strikes), vec![(100, false)]);
+        let strikes = calc.calculate(&mut phoenix, &mut unit("Phoenix", 100), &melee(100, false));
+        assert_eq!(damage(&strikes), vec![(0, false)]);
+        let strikes = calc.calculate(&mut phoenix, &mut unit("Dragon", 100), &melee(100, false));
+        assert_eq!(damage(&strikes), vec![(150, false)]);
+        assert_eq!(strikes[0].breakdown.last().unwrap(), "Ice resistance: -50%");
+    }
+
+    #[test]
+    fn ranged_and_melee() {
+        let mut calc = Calc::default();
+        calc.update(
+            r#"{
+                "Peasant": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": ""},
+                "Archer": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": "",
+                           "abilities": ["shooter"]}
+            }"#,
+        );
+        let attack: Attack = serde_json::from_str(r#"{"mode": "ranged"}"#).unwrap();
+        assert_eq!(attack.mode, AttackMode::Ranged);
+        let old: Attack = serde_json::from_str(r#"{"mode": "Ranged"}"#).unwrap();
+        assert_eq!(old.mode, AttackMode::Ranged);
+        assert_eq!(serde_json::to_value(attack.mode).unwrap(), "ranged");
+        let mut defender = unit("Peasant", 100);
+        let mut archer = unit("Archer", 100);
+        let mut peasant = unit("Peasant", 100);
+        let ranged = Attack {
+            retaliation: true,
+            mode: AttackMode::Ranged,
+            ..Default::default()
+        };
+
+        assert!(!calc.will_retaliate(&defender, &archer, &ranged));
+        let strikes = calc.calculate(&mut defender, &mut archer, &ranged);
+        assert_eq!(damage(&strikes), vec![(100, false)]);
+
+        // only shooters can attack at range
+        assert!(calc.will_retaliate(&defender, &peasant, &ranged));
+
+        let strikes = calc.calculate(
+            &mut defender,
+            &mut archer,
+            &Attack {
+                long_range: true,
+                obstacle: true,
+                ..ranged.clone()
+            },
+        );
+        assert_eq!(damage(&strikes), vec![(25, false)]);
+
+        let strikes = calc.calculate(&mut defender, &mut archer, &melee(100, false));
+        assert_eq!(damage(&strikes), vec![(50, false)]);
+
+        // a shooter retaliating in melee takes the penalty too
+        let strikes = calc.calculate(&mut archer, &mut peasant, &melee(100, true));
+        assert_eq!(damage(&strikes), vec![(100, false), (45, true)]);
+    }
+
+    #[test]
+    fn heal_and_resurrect() {
+        let calc = calc();
+        let mut peasant = Unit {
+            damage_left: 4,
+            initial: 5,
+            ..unit("Peasant", 2)
+        };
+
+        assert_eq!(calc.heal(&mut peasant, &Stats::default(), 100, false), 4);
+        assert_eq!((peasant.value, peasant.damage_left), (2, 0));
+
+        peasant.damage_left = 4;
+        assert_eq!(calc.heal(&mut peasant, &Stats::default(), 19, true), 19);
+        assert_eq!((peasant.value, peasant.damage_left), (4, 5));
+
+        assert_eq!(calc.heal(&mut peasant, &Stats::default(), 100, true), 15);
+        assert_eq!((peasant.value, peasant.damage_left), (5, 0));
+
+        let mut dead = Unit {
+            initial: 3,
+            ..unit("Peasant", 0)
+        };
+        assert_eq!(calc.heal(&mut dead, &Stats::default(), 100, false), 0);
+        assert_eq!(calc.heal(&mut dead, &Stats::default(), 100, true), 30);
+        assert_eq!(dead.value, 3);
+    }
+
+    #[test]
+    fn casualties() {
+        let calc = calc();
+        let mut attacker = unit("Peasant", 10);
+        let mut defender = unit("Peasant", 5);
+
+        let strikes = calc.calculate(&mut defender, &mut attacker, &melee(1000, true));
+        assert_eq!(damage(&strikes), vec![(100, false)]);
+        assert_eq!(strikes[0].killed, 5);
+        assert_eq!((defender.value, defender.damage_left), (0, 0));
+        assert_eq!((attacker.kills, attacker.damage_dealt), (5, 100));
+        assert_eq!((defender.losses, defender.damage_taken), (5, 100));
+    }
+
+    #[test]
+    fn area_attack() {
+        let calc = calc();
+        let mut attacker = Unit {
+            stats: Stats {
+                max_dmg: 9,
+                ..Default::default()
+            },
+            ..unit("Peasant", 100)
+        };
+        let mut first = unit("Peasant", 100);
+        let mut second = unit("Griffin", 100);
+
+        let strikes = calc.calculate_area(
+            &mut [
+                (&mut first, melee(100, true)),
+                (&mut second, melee(100, true)),
+            ],
+            &mut attacker,
+            true,
+        );
+        assert_eq!(strikes.len(), 2);
+        assert_eq!(strikes[0].damage, strikes[1].damage);
+        assert!(strikes.iter().all(|strike| !strike.retaliation));
+        assert_eq!(first.value, second.value);
+        assert_eq!(second.retaliated, 0);
+
+        let strikes = calc.calculate_area(
+            &mut [
+                (&mut first, melee(100, false)),
+                (&mut second, melee(50, false)),
+            ],
+            &mut unit("Peasant", 100),
+            false,
+        );
+        assert_eq!((strikes[0].damage, strikes[1].damage), (100, 50));
+    }
+
+    #[test]
+    fn split_and_merge() {
+        let calc = calc();
+        let mut stack = Unit {
+            shields: vec![Shield {
+                amount: 30,
+                ..Default::default()
+            }],
+            damage_left: 4,
+            ..unit("Peasant", 10)
+        };
+        let mut split = stack.split(4).unwrap();
+        assert_eq!((stack.value, stack.initial, stack.shield(None)), (6, 6, 18));
+        assert_eq!(
+            (split.value, split.damage_left, split.shield(None)),
+            (4, 0, 12)
+        );
+        assert!(stack.split(6).is_none() && stack.split(0).is_none());
+
+        split.stats.attack = 4;
+        split.damage_left = 7;
+        calc.merge(&mut stack, &split, &Stats::default()).unwrap();
+        assert_eq!((stack.value, stack.damage_left), (9, 1));
+        assert_eq!((stack.stats.attack, stack.shield(None)), (1, 30));
+        assert!(calc
+            .merge(&mut stack, &unit("Griffin", 1), &Stats::default())
+            .is_err());
+    }
+
+    #[test]
+    fn hero_bonus() {
+        let calc = calc();
+        let hero = Hero {
+            stats: Stats {
+                attack: 5,
+                defense: 10,
+                ..Default::default()
+            },
+            artifacts: vec![Artifact {
+                name: "sword".to_string(),
+                stats: Stats {
+                    attack: 5,
+                    ..Default::default()
+                },
+            }],
+            ..Default::default()
+        };
+        let attack = Attack {
+            attacker_hero: hero.bonus(),
+            ..melee(100, true)
+        };
+        let mut attacker = unit("Peasant", 10);
+        let mut defender = unit("Peasant", 10);
+        assert_eq!(
+            calc.effective_stats(&attacker, &attack.attacker_hero)
+                .unwrap()
+                .attack,
+            20
+        );
+        // atk 20 vs def 10 for the attack, atk 10 vs def 20 for the retaliation
+        let strikes = calc.calculate(&mut defender, &mut attacker, &attack);
+        assert_eq!(damage(&strikes), vec![(15, false), (6, true)]);
+    }
+
+    #[test]
+    fn terrain() {
+        let mut calc = calc();
+        let peasant = calc.classes.get_mut("Peasant").unwrap();
+        peasant.native_terrains = vec!["grass".to_string()];
+        peasant.terrain_bonuses.insert(
+            "swamp".to_string(),
+            Stats {
+                speed: -2,
+                ..Default::default()
+            },
+        );
+        let unit = unit("Peasant", 1);
+        let hero = Stats::default();
+        assert_eq!(calc.terrains(), vec!["grass", "swamp"]);
+        assert_eq!(calc.effective_stats(&unit, &hero).unwrap().attack, 10);
+
+        calc.terrain = Some("grass".to_string());
+        let stats = calc.effective_stats(&unit, &hero).unwrap();
+        assert_eq!((stats.attack, stats.defense, stats.speed), (11, 11, 1));
+
+        calc.terrain = Some("swamp".to_string());
+        let stats = calc.effective_stats(&unit, &hero).unwrap();
+        assert_eq!((stats.attack, stats.speed), (10, -2));
+    }
+
+    #[test]
+    fn morale() {
+        let mut calc = calc();
+        for (class, faction) in [
+            ("Peasant", "castle"),
+            ("Griffin", "castle"),
+            ("Hydra", "swamp"),
+        ] {
+            calc.classes.get_mut(class).unwrap().faction = faction.to_string();
+        }
+        calc.classes.get_mut("Peasant").unwrap().morale = 20;
+        calc.classes
+            .get_mut("Angel")
+            .unwrap()
+            .abilities
+            .push(Ability::Undead);
+        let hero = Stats::default();
+        let castle = vec![Some(unit("Peasant", 1)), Some(unit("Griffin", 1))];
+        assert_eq!(calc.morale(&unit("Peasant", 1), &hero, &castle), 20);
+        let mixed = vec![
+            Some(unit("Peasant", 1)),
+            Some(unit("Hydra", 1)),
+            Some(unit("Angel", 1)),
+        ];
+        assert_eq!(calc.morale(&unit("Peasant", 1), &hero, &mixed), 0);
+        assert_eq!(calc.morale(&unit("Angel", 1), &hero, &mixed), 0);
+
+        assert_eq!(calc.roll_morale(100), Morale::ExtraTurn);
+        assert_eq!(calc.roll_morale(-100), Morale::Frozen);
+    }
+
+    #[test]
+    fn turn_flow() {
+        let calc = calc();
+        let hero = Stats::default();
+        let fast = Stats {
+            speed: 5,
+            ..Default::default()
+        };
+        let mut team0 = vec![Some(unit("Peasant", 1)), None, Some(unit("Griffin", 0))];
+        let team1 = vec![Some(unit("Hydra", 1))];
+        let order = calc.turn_order(&[(&team0, &hero), (&team1, &fast)]);
+        assert_eq!(order, vec![(1, 0), (0, 0)]);
+
+        let peasant = team0[0].as_mut().unwrap();
+        assert_eq!(calc.start_turn(peasant, 100), Morale::ExtraTurn);
+        peasant.end_turn();
+        assert!(!peasant.acted);
+        assert_eq!(calc.start_turn(peasant, 100), Morale::Normal);
+        peasant.end_turn();
+        assert!(peasant.acted);
+        assert_eq!(calc.turn_order(&[(&team0, &hero)]), vec![]);
+
+        let mut peasant = unit("Peasant", 1);
+        assert_eq!(calc.start_turn(&mut peasant, -100), Morale::Frozen);
+        assert!(peasant.acted);
+        peasant.new_round();
+        assert!(!peasant.acted && peasant.morale.is_none());
+    }
+
+    #[test]
+    fn spells() {
+        let mut calc = calc();
+        let errs = calc.update_spells(
+            r#"{
+                "Fireball": {"kind": "damage", "base": 10, "per_power": 10, "damage_type": "fire"},
+                "Cure": {"kind": "heal", "base": 5, "per_power": 5},
+                "Slow": {"kind": "debuff", "base": 1, "per_power": 1, "effect": {"delta": {"speed": -2}}},
+                "Broken": {"kind": "summon"}
+            }"#,
+        );
+        assert_eq!(errs, 1);
+        let hero = Stats::default();
+        let peasant = calc.classes.get_mut("Peasant").unwrap();
+        peasant.spell_resistance = 50;
+        peasant.resistances.insert(DamageType::Fire, 50);
+
+        let mut target = unit("Peasant", 10);
+        let Cast::Damage(strike) =
+            calc.cast(&calc.spells["Fireball"], "Fireball", 2, &mut target, &hero)
+        else {
+            panic!("not a damage spell");
+        };
+        assert_eq!((strike.damage, target.value), (7, 10));
+        target.shields.push(Shield {
+            amount: 10,
+            ..Default::default()
+        });
+        calc.cast(&calc.spells["Fireball"], "Fireball", 2, &mut target, &hero);
+        assert_eq!((target.shield(None), target.damage_left), (3, 7));
+
+        assert!(matches!(
+            calc.cast(&calc.spells["Cure"], "Cure", 0, &mut target, &hero),
+            Cast::Heal(5, 0)
+        ));
+
+        let mut griffin = unit("Griffin", 1);
+        assert!(matches!(
+            calc.cast(&calc.spells["Slow"], "Slow", 2, &mut griffin, &hero),
+            Cast::Effect(3)
+        ));
+        assert_eq!(griffin.effects[0].name, "Slow");
+        assert_eq!(calc.effective_stats(&griffin, &hero).unwrap().speed, -2);
+    }
+
+    #[test]
+    fn shields() {
+        let shield = |source: &str, amount, rounds, damage_type| Shield {
+            source: source.to_string(),
+            amount,
+            rounds,
+            damage_type,
+        };
+        let mut unit = Unit {
+            shields: vec![
+                shield("aura", 10, None, None),
+                shield("ward", 10, Some(1), None),
+                shield("fire ward", 10, None, Some(DamageType::Fire)),
+            ],
+            ..unit("Peasant", 1)
+        };
+        assert_eq!(unit.shield(None), 30);
+        assert_eq!(unit.shield(Some(DamageType::Ice)), 20);
+
+        let mut breakdown = Vec::new();
+        assert_eq!(unit.absorb(15.0, DamageType::Fire, &mut breakdown), 0.0);
+        assert_eq!(
+            breakdown,
+            vec!["absorbed by fire ward: 10", "absorbed by ward: 5"]
+        );
+        assert_eq!(unit.absorb(20.0, DamageType::Ice, &mut breakdown), 5.0);
+        assert_eq!(unit.shields, vec![]);
+
+        unit.shields = vec![
+            shield("ward", 10, Some(1), None),
+            shield("aura", 10, None, None),
+        ];
+        unit.new_round();
+        assert_eq!(unit.shields, vec![shield("aura", 10, None, None)]);
+    }
+
+    #[test]
+    fn seeded_simulation() {
+        let mut calc = calc();
+        calc.classes.get_mut("Peasant").unwrap().max_dmg = 10;
+        let (attacker, defender) = (unit("Peasant", 10), unit("Peasant", 100));
+        calc.seed(7);
+        let first = calc.simulate(&defender, &attacker, &melee(100, true), 50);
+        calc.seed(7);
+        let second = calc.simulate(&defender, &attacker, &melee(100, true), 50);
+        assert_eq!(first.avg_damage, second.avg_damage);
+        assert!(first.min_damage >= 10 && first.max_damage <= 100);
+        assert!(first.min_damage < first.max_damage);
+        assert!(first.avg_retaliation_damage > 0.0);
+        assert_eq!(first.defender_wiped, 0.0);
+    }
+
+    #[test]
+    fn old_saved_units() {
+        let unit: Unit = serde_json::from_str(
+            r#"{"name": "Peasant", "stats": {"absorb": 25}, "value": 42, "damage_left": 3}"#,
+        )
+        .unwrap();
+        assert_eq!((unit.value, unit.initial, unit.losses), (42, 42, 0));
+        assert_eq!((unit.stats.absorb, unit.shield(None)), (0, 25));
+        let mut hurt = unit.clone();
+        hurt.value = 40;
+        let json = serde_json::to_string(&hurt).unwrap();
+        let loaded: Unit = serde_json::from_str(&json).unwrap();
+        assert_eq!((loaded.value, loaded.initial), (40, 42));
+    }
+
+    #[test]
+    fn generations() {
+        let mut calc = calc();
+        let loaded = calc.generation();
+        assert_eq!(calc.clone().generation(), loaded);
+        assert_ne!(Calc::default().generation(), loaded);
+        calc.update(r#"{}"#);
+        assert_ne!(calc.generation(), loaded);
+        let failed = calc.generation();
+        calc.update("not json");
+        assert_eq!(calc.generation(), failed);
+    }
+
+    #[test]
+    fn ratings() {
+        let calc = calc();
+        let peasant = calc.rating("Peasant", "Peasant", 10).unwrap();
+        assert_eq!(peasant.avg_damage, 1.0);
+        assert_eq!(peasant.effective_health, 12.5);
+        assert_eq!(peasant.damage_per_health, 0.1);
+        let crusader = calc.rating("Crusader", "Peasant", 10).unwrap();
+        assert_eq!(crusader.damage_per_health, 0.2);
+        assert!(crusader.power > peasant.power);
+        assert_eq!(
+            calc.rating("Angel", "Peasant", 10)
+                .unwrap()
+                .damage_per_health,
+            0.15
+        );
+        assert_eq!(
+            calc.rating("Angel", "Dragon", 10)
+                .unwrap()
+                .damage_per_health,
+            0.0
+        );
+        assert!(calc.rating("Dragon", "Peasant", 10).is_none());
+
+        let mut calc = calc;
+        let peasant = calc.classes.get_mut("Peasant").unwrap();
+        peasant.max_dmg = i32::MAX;
+        peasant.health = 1;
+        let titan = calc.rating("Peasant", "Peasant", 10).unwrap();
+        assert!(titan.damage_per_health > 1e6, "{}", titan.damage_per_health);
+    }
+}
@@ -0,0 +1,287 @@
+//! Point costs of teams, fights played out to the end and enemy teams made to a budget.
+
+use crate::calc::{Ability, AttackMode, Calc, Unit};
+use crate::session::Team;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Fights still going after this many rounds count as lost for the players.
+pub const MAX_ROUNDS: i32 = 30;
+
+/// How the players fared over many simulated fights against one enemy team.
+#[derive(serde::Serialize, Default, Clone)]
+pub struct Difficulty {
+    pub runs: u32,
+    /// Share of runs the players won.
+    pub win_rate: f32,
+    pub avg_rounds: f32,
+    /// Share of the players' points lost, won runs only.
+    pub avg_losses: f32,
+}
+
+impl Difficulty {
+    pub fn rating(&self) -> &'static str {
+        match (self.win_rate, self.avg_losses) {
+            (win, _) if win < 0.5 => "deadly",
+            (win, losses) if win < 0.9 || losses > 0.5 => "hard",
+            (_, losses) if losses > 0.2 => "medium",
+            (_, losses) if losses > 0.05 => "easy",
+            _ => "trivial",
+        }
+    }
+}
+
+/// Points of every creature still alive on `team`, unknown classes count as free.
+/// In i64 as any count can be typed in, past `i64::MAX` it saturates.
+pub fn team_cost(calc: &Calc, team: &Team) -> i64 {
+    team.units
+        .iter()
+        .flatten()
+        .filter(|unit| unit.value > 0)
+        .map(|unit| i64::from(unit.value) * i64::from(calc.cost(&unit.name).unwrap_or(0)))
+        .fold(0, i64::saturating_add)
+}
+
+fn alive(team: &Team) -> bool {
+    team.units.iter().flatten().any(|unit| unit.value > 0)
+}
+
+/// Plays out a fight in turn order, every unit attacks a random enemy and is struck back.
+/// Shooters shoot, morale is rolled, spells and the battlefield are left out.
+/// Returns the winning side, if any, and the rounds it took, a side with nothing alive loses in 0.
+pub fn fight(calc: &Calc, teams: &mut [Team; 2]) -> (Option<usize>, i32) {
+    match (alive(&teams[0]), alive(&teams[1])) {
+        (true, true) => {}
+        (true, false) => return (Some(0), 0),
+        (false, true) => return (Some(1), 0),
+        (false, false) => return (None, 0),
+    }
+    for round in 1..=MAX_ROUNDS {
+        for team in teams.iter_mut() {
+            team.new_round();
+        }
+        loop {
+            let heroes = [teams[0].hero_bonus(), teams[1].hero_bonus()];
+            let order = calc.turn_order(&[
+                (teams[0].units.as_slice(), &heroes[0]),
+                (teams[1].units.as_slice(), &heroes[1]),
+            ]);
+            let Some(&(side, slot)) = order.first() else {
+                break;
+            };
+            let enemy = 1 - side;
+            let Some(mut attacker) = teams[side].units[slot].clone() else {
+                break;
+            };
+            if attacker.morale.is_none() {
+                let morale = calc.morale(&attacker, &heroes[side], &teams[side].units);
+                calc.start_turn(&mut attacker, morale);
+            }
+            if !attacker.acted {
+                let targets = teams[enemy]
+                    .units
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, unit)| unit.as_ref().is_some_and(|unit| unit.value > 0))
+                    .map(|(slot, _)| slot)
+                    .collect::<Vec<_>>();
+                if targets.is_empty() {
+                    return (Some(side), round);
+                }
+                let target = targets[calc.rng().gen_range(0..targets.len())];
+                let mut defender = teams[enemy].units[target].clone().unwrap_or_default();
+                let mut attack = teams[side].attack(&teams[enemy]);
+                attack.retaliation = true;
+                attack.mode = match calc.has_ability(&attacker, &Ability::Shooter) {
+                    true => AttackMode::Ranged,
+                    false => AttackMode::Melee,
+                };
+                calc.calculate(&mut defender, &mut attacker, &attack);
+                attacker.end_turn();
+                teams[enemy].units[target] = Some(defender);
+            }
+            teams[side].units[slot] = Some(attacker);
+            if !alive(&teams[enemy]) {
+                return (Some(side), round);
+            }
+            if !alive(&teams[side]) {
+                return (Some(enemy), round);
+            }
+        }
+    }
+    (None, MAX_ROUNDS)
+}
+
+/// Fights `players` against `enemies` `runs` times, every time with fresh copies of both.
+pub fn difficulty(calc: &Calc, players: &Team, enemies: &Team, runs: u32) -> Difficulty {
+    let mut result = Difficulty {
+        runs,
+        ..Default::default()
+    };
+    let worth = team_cost(calc, players).max(1) as f32;
+    let mut wins = 0;
+    for _ in 0..runs {
+        let mut teams = [players.clone(), enemies.clone()];
+        let (winner, rounds) = fight(calc, &mut teams);
+        result.avg_rounds += rounds as f32;
+        if winner == Some(0) {
+            wins += 1;
+            result.avg_losses += 1.0 - team_cost(calc, &teams[0]) as f32 / worth;
+        }
+    }
+    if runs == 0 {
+        return result;
+    }
+    result.win_rate = wins as f32 / runs as f32;
+    result.avg_rounds /= runs as f32;
+    result.avg_losses = match wins {
+        0 => 1.0,
+        wins => result.avg_losses / wins as f32,
+    };
+    result
+}
+
+/// Random enemy team of up to `slots` stacks worth at most `budget` points,
+/// the points are shared out evenly and the rest goes to the cheapest stack.
+/// No stack grows past `i32::MAX` creatures, whatever the budget.
+pub fn generate(calc: &Calc, budget: i64, slots: usize) -> Team {
+    let mut team = Team::new(slots);
+    team.name = "encounter".to_string();
+    let mut classes = calc
+        .classes
+        .keys()
+        .filter_map(|class| Some((class.as_str(), i64::from(calc.cost(class)?))))
+        .filter(|(_, cost)| *cost <= budget)
+        .collect::<Vec<_>>();
+    // Sorted first so the same seed picks the same classes.
+    classes.sort();
+    if classes.is_empty() || slots == 0 {
+        return team;
+    }
+    let mut rng = calc.rng();
+    classes.shuffle(&mut *rng);
+    let stacks = rng.gen_range(1..=slots.min(classes.len()));
+    drop(rng);
+    let picked = &classes[..stacks];
+    let share = budget / stacks as i64;
+    let mut left = budget;
+    for (slot, (class, cost)) in picked.iter().enumerate() {
+        let count = (share / cost).min(i32::MAX.into());
+        left -= count * cost;
+        team.units[slot] = Some(Unit {
+            name: class.to_string(),
+            value: count as i32,
+            initial: count as i32,
+            ..Default::default()
+        });
+    }
+    let Some((cheapest, (_, cost))) = picked.iter().enumerate().min_by_key(|(_, (_, cost))| *cost)
+    else {
+        return team;
+    };
+    if let Some(unit) = team.units[cheapest].as_mut() {
+        unit.value = (i64::from(unit.value) + left / cost).min(i32::MAX.into()) as i32;
+        unit.initial = unit.value;
+    }
+    team.units
+        .retain(|unit| unit.as_ref().map_or(true, |unit| unit.value > 0));
+    team.units.resize(slots, None);
+    team
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc() -> Calc {
+        let mut calc = Calc::default();
+        calc.update(
+            r#"{
+                "Peasant": {"attack": 1, "defence": 1, "min_dmg": 1, "max_dmg": 1, "health": 3, "description": ""},
+                "Archer": {"attack": 4, "defence": 2, "min_dmg": 2, "max_dmg": 3, "health": 10, "description": "",
+                           "abilities": ["shooter"]},
+                "Angel": {"attack": 20, "defence": 20, "min_dmg": 50, "max_dmg": 50, "health": 200, "description": "",
+                          "cost": 1000}
+            }"#,
+        );
+        calc.seed(7);
+        calc
+    }
+
+    fn team(units: &[(&str, i32)]) -> Team {
+        let mut team = Team::new(units.len());
+        for (slot, (class, count)) in units.iter().enumerate() {
+            team.units[slot] = Some(Unit {
+                name: class.to_string(),
+                value: *count,
+                initial: *count,
+                ..Default::default()
+            });
+        }
+        team
+    }
+
+    #[test]
+    fn costs() {
+        let calc = calc();
+        assert_eq!(calc.cost("Angel"), Some(1000));
+        assert_eq!(calc.cost("Peasant"), Some(2));
+        assert!(calc.cost("Archer") > calc.cost("Peasant"));
+        assert_eq!(calc.cost("Dragon"), None);
+        let mixed = team(&[("Angel", 2), ("Peasant", 10), ("Dragon", 5)]);
+        assert_eq!(team_cost(&calc, &mixed), 2020);
+        let legions = team(&[("Angel", i32::MAX), ("Peasant", i32::MAX)]);
+        assert_eq!(team_cost(&calc, &legions), i64::from(i32::MAX) * 1002);
+        let legions = team(&[("Angel", i32::MAX); 8]);
+        assert_eq!(team_cost(&calc, &legions), i64::from(i32::MAX) * 8000);
+    }
+
+    #[test]
+    fn difficulty_ratings() {
+        let calc = calc();
+        let angels = team(&[("Angel", 5)]);
+        let peasants = team(&[("Peasant", 20)]);
+        let easy = difficulty(&calc, &angels, &peasants, 20);
+        assert_eq!(easy.win_rate, 1.0);
+        assert_eq!(easy.rating(), "trivial");
+        let deadly = difficulty(&calc, &peasants, &angels, 20);
+        assert_eq!(deadly.win_rate, 0.0);
+        assert_eq!(deadly.rating(), "deadly");
+
+        let mut teams = [angels.clone(), peasants];
+        assert_eq!(fight(&calc, &mut teams).0, Some(0));
+        assert!(!alive(&teams[1]));
+
+        let nobody = Team::new(4);
+        let unopposed = difficulty(&calc, &angels, &nobody, 5);
+        assert_eq!((unopposed.win_rate, unopposed.avg_rounds), (1.0, 0.0));
+        assert_eq!(difficulty(&calc, &nobody, &angels, 5).win_rate, 0.0);
+        assert_eq!(fight(&calc, &mut [nobody.clone(), nobody]), (None, 0));
+    }
+
+    #[test]
+    fn generated_teams_fit_the_budget() {
+        let calc = calc();
+        for budget in [50, 500, 3000] {
+            let team = generate(&calc, budget, 4);
+            assert_eq!(team.units.len(), 4);
+            let cost = team_cost(&calc, &team);
+            let cheapest = team
+                .units
+                .iter()
+                .flatten()
+                .filter_map(|unit| calc.cost(&unit.name))
+                .min()
+                .unwrap();
+            assert!(
+                cost <= budget && budget - cost < cheapest.into(),
+                "{}",
+                budget
+            );
+        }
+        assert!(generate(&calc, 1, 4).units.iter().all(Option::is_none));
+        let huge = generate(&calc, i64::MAX, 4);
+        assert!(huge.units.iter().flatten().all(|unit| unit.value > 0));
+        assert!(team_cost(&calc, &huge) > i64::from(i32::MAX));
+    }
+}
@@ -0,0 +1,8 @@
+#![warn(clippy::all, rust_2018_idioms)]
+//! Combat engine of the damage calculator: classes, units, attacks and battle sessions.
+//! Has no GUI dependencies, the eframe app is one of its users.
+
+pub mod battlefield;
+pub mod calc;
+pub mod encounter;
+pub mod session;
@@ -0,0 +1,455 @@
+use crate::battlefield::Battlefield;
+use crate::calc::{Attack, AttackMode, Calc, Hero, Stats, Strike, Unit};
+
+/// Everything about a battle that the GUI and the terminal share, saved as json.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Session {
+    pub calc: Calc,
+    pub teams: Vec<Team>,
+    pub round: i32,
+    pub log: Vec<String>,
+    pub battlefield: Battlefield,
+    /// Team index and slot of the unit whose turn it is.
+    pub active: Option<(usize, usize)>,
+}
+
+impl Session {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    /// Name of a slot as typed in the terminal, `A0` is the first slot of the first team.
+    /// Teams after `Z` are lettered like spreadsheet columns, `AA`, `AB` and so on.
+    pub fn address((team, slot): (usize, usize)) -> String {
+        let mut letters = Vec::new();
+        let mut rest = team + 1;
+        while rest > 0 {
+            rest -= 1;
+            letters.push((b'A' + (rest % 26) as u8) as char);
+            rest /= 26;
+        }
+        letters.iter().rev().collect::<String>() + &slot.to_string()
+    }
+
+    pub fn parse_address(&self, address: &str) -> Result<(usize, usize), String> {
+        let split = address
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(address.len());
+        let (letters, slot) = address.split_at(split);
+        if letters.is_empty() {
+            return Err(format!("`{}` does not start with a team letter", address));
+        }
+        let slot = slot
+            .parse::<usize>()
+            .map_err(|_| format!("`{}` does not end with a slot number", address))?;
+        Ok((self.parse_team(letters)?, slot))
+    }
+
+    /// Index of the team with the letters of `address`, like `B` or `AA`.
+    pub fn parse_team(&self, letters: &str) -> Result<usize, String> {
+        if letters.is_empty() || !letters.bytes().all(|letter| letter.is_ascii_alphabetic()) {
+            return Err(format!("`{}` is not a team letter", letters));
+        }
+        let team = letters
+            .bytes()
+            .try_fold(0usize, |team, letter| {
+                team.checked_mul(26)?
+                    .checked_add((letter.to_ascii_uppercase() - b'A') as usize + 1)
+            })
+            .map_or(usize::MAX, |team| team - 1);
+        if team >= self.teams.len() {
+            return Err(format!("no team {}", letters));
+        }
+        Ok(team)
+    }
+
+    pub fn unit(&self, (team, slot): (usize, usize)) -> Option<&Unit> {
+        self.teams.get(team)?.units.get(slot)?.as_ref()
+    }
+
+    fn unit_mut(&mut self, (team, slot): (usize, usize)) -> Option<&mut Unit> {
+        self.teams.get_mut(team)?.units.get_mut(slot)?.as_mut()
+    }
+
+    pub fn label(&self, (team, slot): (usize, usize)) -> String {
+        match (self.teams.get(team), self.unit((team, slot))) {
+            (Some(team), Some(unit)) => unit_label(&team.name, slot, unit),
+            _ => Self::address((team, slot)),
+        }
+    }
+
+    /// Resolves an attack with the settings of the attacker's team and logs it.
+    pub fn attack(
+        &mut self,
+        attacker: (usize, usize),
+        defender: (usize, usize),
+    ) -> Result<Vec<Strike>, String> {
+        if attacker == defender {
+            return Err("a unit can't attack itself".to_string());
+        }
+        let mut attacker_unit = self
+            .unit(attacker)
+            .cloned()
+            .ok_or_else(|| format!("{} is empty", Self::address(attacker)))?;
+        let mut defender_unit = self
+            .unit(defender)
+            .cloned()
+            .ok_or_else(|| format!("{} is empty", Self::address(defender)))?;
+        let attack = self.teams[attacker.0].attack(&self.teams[defender.0]);
+        let strikes = self
+            .calc
+            .calculate(&mut defender_unit, &mut attacker_unit, &attack);
+        let line = attack_log(
+            self.round,
+            &self.label(attacker),
+            &self.label(defender),
+            &strikes,
+        );
+        self.log.push(line);
+        if let Some(unit) = self.unit_mut(attacker) {
+            *unit = attacker_unit;
+        }
+        if let Some(unit) = self.unit_mut(defender) {
+            *unit = defender_unit;
+        }
+        Ok(strikes)
+    }
+
+    pub fn new_round(&mut self) {
+        self.round += 1;
+        self.active = None;
+        for team in self.teams.iter_mut() {
+            team.new_round();
+        }
+    }
+}
+
+/// Side of a battle, with the attack settings the GM picked for it.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct Team {
+    pub name: String,
+    pub select: usize,
+    pub units: Vec<Option<Unit>>,
+    pub percent: i32,
+    pub retaliation: bool,
+    pub second_select: usize,
+    #[serde(default)]
+    pub mode: AttackMode,
+    #[serde(default)]
+    pub long_range: bool,
+    #[serde(default)]
+    pub obstacle: bool,
+    /// Enemy slots hit by the area attack.
+    #[serde(default)]
+    pub area_targets: Vec<usize>,
+    /// Own slots hit by the area attack, only with friendly fire.
+    #[serde(default)]
+    pub area_allies: Vec<usize>,
+    #[serde(default)]
+    pub area_roll_once: bool,
+    #[serde(default)]
+    pub split_count: i32,
+    #[serde(default)]
+    pub hero: Option<Hero>,
+    #[serde(default)]
+    pub spell: String,
+    #[serde(default)]
+    pub spell_power: i32,
+}
+impl Team {
+    pub fn new_round(&mut self) {
+        for unit in self.units.iter_mut().flatten() {
+            unit.new_round();
+        }
+    }
+    pub fn new(count: usize) -> Self {
+        let mut units = Vec::with_capacity(count);
+        for _ in 0..count {
+            units.push(None);
+        }
+        Self {
+            name: "team".to_string(),
+            select: 0,
+            units,
+            percent: 100,
+            retaliation: false,
+            second_select: 0,
+            mode: AttackMode::Melee,
+            long_range: false,
+            obstacle: false,
+            area_targets: Vec::new(),
+            area_allies: Vec::new(),
+            area_roll_once: false,
+            split_count: 1,
+            hero: None,
+            spell: String::new(),
+            spell_power: 0,
+        }
+    }
+    pub fn attack(&self, enemy_team: &Team) -> Attack {
+        Attack {
+            percent: self.percent,
+            retaliation: enemy_team.retaliation,
+            mode: self.mode,
+            long_range: self.long_range,
+            obstacle: self.obstacle,
+            attacker_hero: self.hero_bonus(),
+            defender_hero: enemy_team.hero_bonus(),
+        }
+    }
+    pub fn hero_bonus(&self) -> Stats {
+        self.hero.as_ref().map(Hero::bonus).unwrap_or_default()
+    }
+    /// Moves `count` creatures of `slot` into the first empty slot and returns that slot.
+    pub fn split(&mut self, slot: usize, count: i32) -> Result<usize, &'static str> {
+        let empty = self
+            .units
+            .iter()
+            .position(Option::is_none)
+            .ok_or("no empty slot")?;
+        let unit = self
+            .units
+            .get_mut(slot)
+            .and_then(Option::as_mut)
+            .ok_or("no unit")?;
+        let new = unit
+            .split(count)
+            .ok_or("both stacks must keep a creature")?;
+        self.units[empty] = Some(new);
+        Ok(empty)
+    }
+    /// Folds the stack in `from` into the one in `into` and empties `from`.
+    pub fn merge(&mut self, calc: &Calc, from: usize, into: usize) -> Result<(), &'static str> {
+        if from == into {
+            return Err("same slot");
+        }
+        let hero = self.hero_bonus();
+        let source = self.units.get(from).cloned().flatten().ok_or("no unit")?;
+        let target = self
+            .units
+            .get_mut(into)
+            .and_then(Option::as_mut)
+            .ok_or("no unit")?;
+        calc.merge(target, &source, &hero)?;
+        self.units[from] = None;
+        Ok(())
+    }
+}
+
+pub fn unit_label(team_name: &str, slot: usize, unit: &Unit) -> String {
+    format!("{}_{}#{}", team_name, slot, unit.name)
+}
+
+/// How a stack looks to players who don't know its exact size.
+pub fn approximate_count(count: i32) -> &'static str {
+    match count {
+        i32::MIN..=0 => "none left",
+        1..=4 => "a few",
+        5..=9 => "several",
+        10..=19 => "a pack",
+        20..=49 => "lots",
+        50..=99 => "a horde",
+        100..=249 => "a throng",
+        250..=499 => "a swarm",
+        500..=999 => "zounds",
+        _ => "a legion",
+    }
+}
+
+/// What the players may see of a battle: names, rough stack sizes and whose turn it is.
+/// Sent to spectators instead of the `Session`, which holds every stat.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, PartialEq, Debug)]
+pub struct SpectatorView {
+    pub round: i32,
+    /// Team and unit whose turn it is.
+    pub turn: Option<(String, String)>,
+    pub teams: Vec<SpectatedTeam>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, PartialEq, Debug)]
+pub struct SpectatedTeam {
+    pub name: String,
+    pub hero: Option<String>,
+    pub units: Vec<SpectatedUnit>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, PartialEq, Debug)]
+pub struct SpectatedUnit {
+    pub name: String,
+    /// One of `approximate_count`.
+    pub count: String,
+    pub alive: bool,
+    pub active: bool,
+}
+
+impl SpectatorView {
+    /// `teams[sides[0]]` against `teams[sides[1]]`, sides that don't exist are left out.
+    pub fn new(
+        teams: &[Team],
+        sides: [usize; 2],
+        round: i32,
+        active: Option<(usize, usize)>,
+    ) -> Self {
+        let turn = active.and_then(|(team, slot)| {
+            let team = teams.get(team)?;
+            let unit = team.units.get(slot)?.as_ref()?;
+            Some((team.name.clone(), unit.name.clone()))
+        });
+        let teams = sides
+            .iter()
+            .filter_map(|&t| Some((t, teams.get(t)?)))
+            .map(|(t, team)| SpectatedTeam {
+                name: team.name.clone(),
+                hero: team.hero.as_ref().map(|hero| hero.name.clone()),
+                units: team
+                    .units
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(slot, unit)| Some((slot, unit.as_ref()?)))
+                    .map(|(slot, unit)| SpectatedUnit {
+                        name: unit.name.clone(),
+                        count: approximate_count(unit.value).to_string(),
+                        alive: unit.value > 0,
+                        active: active == Some((t, slot)),
+                    })
+                    .collect(),
+            })
+            .collect();
+        SpectatorView { round, turn, teams }
+    }
+}
+
+pub fn attack_log(round: i32, attacker: &str, defender: &str, strikes: &[Strike]) -> String {
+    let strikes = strikes
+        .iter()
+        .map(|strike| {
+            format!(
+                "{}{} dmg, {} killed",
+                if strike.retaliation {
+                    "retaliation "
+                } else {
+                    ""
+                },
+                strike.damage,
+                strike.killed
+            )
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "r{} {} attacks {}: {}",
+        round,
+        attacker,
+        defender,
+        strikes.join("; ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        let mut session = Session::default();
+        session.calc.update(
+            r#"{"Peasant": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": ""}}"#,
+        );
+        for name in ["red", "blue"] {
+            let mut team = Team::new(2);
+            team.name = name.to_string();
+            team.units[0] = Some(Unit {
+                name: "Peasant".to_string(),
+                value: 10,
+                initial: 10,
+                ..Default::default()
+            });
+            session.teams.push(team);
+        }
+        session
+    }
+
+    #[test]
+    fn addresses() {
+        let session = session();
+        assert_eq!(session.parse_address("b1"), Ok((1, 1)));
+        assert_eq!(Session::address((1, 1)), "B1");
+        assert!(session.parse_address("C0").is_err());
+        assert!(session.parse_address("A").is_err());
+        assert!(session.parse_address("1A").is_err());
+        assert!(session.parse_address("AA0").is_err());
+
+        for (team, address) in [
+            (25, "Z3"),
+            (26, "AA3"),
+            (27, "AB3"),
+            (701, "ZZ3"),
+            (702, "AAA3"),
+        ] {
+            assert_eq!(Session::address((team, 3)), address);
+        }
+        let mut many = Session::default();
+        many.teams.resize(30, Team::new(1));
+        assert_eq!(many.parse_address("ad3"), Ok((29, 3)));
+        assert_eq!(many.parse_address("AA0"), Ok((26, 0)));
+        assert!(many.parse_address("AE0").is_err());
+        assert!(many.parse_address("ZZZZZZZZZZZZZZZ0").is_err());
+        assert_eq!(many.parse_team("ab"), Ok(27));
+        for letters in ["", "A1", "AE", "Ä"] {
+            assert!(many.parse_team(letters).is_err(), "{}", letters);
+        }
+    }
+
+    #[test]
+    fn approximate_counts() {
+        assert_eq!(approximate_count(0), "none left");
+        assert_eq!(approximate_count(4), "a few");
+        assert_eq!(approximate_count(50), "a horde");
+        assert_eq!(approximate_count(99), "a horde");
+        assert_eq!(approximate_count(5000), "a legion");
+    }
+
+    #[test]
+    fn spectator_view() {
+        let mut session = session();
+        session.teams[1].units[0].as_mut().unwrap().value = 0;
+        let view = SpectatorView::new(&session.teams, [1, 0], 2, Some((0, 0)));
+        assert_eq!(view.round, 2);
+        assert_eq!(view.turn, Some(("red".to_string(), "Peasant".to_string())));
+        assert_eq!(view.teams[0].name, "blue");
+        assert_eq!(view.teams[0].units.len(), 1);
+        assert!(!view.teams[0].units[0].alive);
+        let peasants = &view.teams[1].units[0];
+        assert_eq!((peasants.count.as_str(), peasants.active), ("a pack", true));
+
+        let json = serde_json::to_string(&view).unwrap();
+        assert!(!json.contains("attack") && !json.contains("health"));
+        assert_eq!(
+            SpectatorView::new(&session.teams, [0, 5], 1, None)
+                .teams
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn attack_and_reload() {
+        let mut session = session();
+        let strikes = session.attack((0, 0), (1, 0)).unwrap();
+        assert_eq!(strikes[0].damage, 10);
+        assert_eq!(session.unit((1, 0)).unwrap().value, 9);
+        assert_eq!(
+            session.log,
+            vec!["r0 red_0#Peasant attacks blue_0#Peasant: 10 dmg, 1 killed"]
+        );
+        assert!(session.attack((0, 1), (1, 0)).is_err());
+        assert!(session.attack((0, 0), (0, 0)).is_err());
+
+        let loaded = Session::from_json(&session.to_json()).unwrap();
+        assert_eq!(loaded.unit((1, 0)).unwrap().value, 9);
+        assert_eq!(loaded.log.len(), 1);
+    }
+}
@@ -0,0 +1,374 @@
+#![warn(clippy::all, rust_2018_idioms)]
+//! JavaScript bindings of the combat engine for web pages, apart from the eframe app.
+//!
+//! Units, attacks and results cross the boundary as plain objects in the same json shape
+//! the app saves, the typescript definitions below describe the parts a page usually needs.
+
+use damage_calc_core::calc::{Attack, Calc, Simulation, Stats, Strike, Unit};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TYPES: &'static str = r#"
+export type DamageType = "physical" | "fire" | "ice";
+
+export interface Stats {
+    attack?: number;
+    min_dmg?: number;
+    max_dmg?: number;
+    defense?: number;
+    health?: number;
+    luck?: number;
+    leadership?: number;
+    morale?: number;
+    speed?: number;
+    damage_type?: DamageType;
+    resistances?: Partial<Record<DamageType, number>>;
+    spell_resistance?: number;
+    /** Encounter points per creature, derived from the other stats when 0. */
+    cost?: number;
+    desc?: string;
+    [other: string]: unknown;
+}
+
+export interface Unit {
+    /** Class name. */
+    name: string;
+    /** Creatures alive. */
+    value: number;
+    /** Damage taken by the top creature. */
+    damage_left?: number;
+    initial?: number;
+    /** Bonus on top of the class stats. */
+    stats?: Stats;
+    [other: string]: unknown;
+}
+
+export interface Attack {
+    /** Damage percent, 100 by default. */
+    percent?: number;
+    retaliation?: boolean;
+    mode?: "melee" | "ranged";
+    long_range?: boolean;
+    obstacle?: boolean;
+    attacker_hero?: Stats;
+    defender_hero?: Stats;
+}
+
+export interface Strike {
+    damage: number;
+    killed: number;
+    retaliation: boolean;
+    breakdown: string[];
+    text: [string, string];
+}
+
+export interface Outcome {
+    strikes: Strike[];
+    attacker: Unit;
+    defender: Unit;
+}
+
+export interface Preview {
+    retaliation: boolean;
+    ranged: boolean;
+    /** Class stats with every bonus, as used by `calculate`. */
+    attacker: Stats;
+    defender: Stats;
+}
+
+export interface Simulation {
+    runs: number;
+    min_damage: number;
+    max_damage: number;
+    avg_damage: number;
+    avg_killed: number;
+    avg_retaliation_damage: number;
+    avg_retaliation_killed: number;
+    defender_wiped: number;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "Stats")]
+    pub type JsStats;
+    #[wasm_bindgen(typescript_type = "Unit")]
+    pub type JsUnit;
+    #[wasm_bindgen(typescript_type = "Attack | undefined")]
+    pub type JsAttack;
+    #[wasm_bindgen(typescript_type = "Outcome")]
+    pub type JsOutcome;
+    #[wasm_bindgen(typescript_type = "Preview")]
+    pub type JsPreview;
+    #[wasm_bindgen(typescript_type = "Simulation")]
+    pub type JsSimulation;
+    #[wasm_bindgen(typescript_type = "string[]")]
+    pub type JsStrings;
+}
+
+/// Result of `Calculator::calculate`, the units as they are after the attack.
+#[derive(Serialize)]
+pub struct Outcome {
+    pub strikes: Vec<Strike>,
+    pub attacker: Unit,
+    pub defender: Unit,
+}
+
+/// What an attack would look like, without rolling it.
+#[derive(Serialize)]
+pub struct Preview {
+    pub retaliation: bool,
+    pub ranged: bool,
+    pub attacker: Stats,
+    pub defender: Stats,
+}
+
+fn from_js<T: DeserializeOwned>(value: &JsValue) -> Result<T, JsError> {
+    if value.is_undefined() || value.is_null() {
+        return serde_json::from_str("{}").map_err(|e| JsError::new(&e.to_string()));
+    }
+    let json = js_sys::JSON::stringify(value)
+        .map_err(|_| JsError::new("value can't be turned into json"))?;
+    serde_json::from_str(&String::from(json)).map_err(|e| JsError::new(&e.to_string()))
+}
+
+fn to_js<T: Serialize, R: JsCast>(value: &T) -> Result<R, JsError> {
+    let json = serde_json::to_string(value).map_err(|e| JsError::new(&e.to_string()))?;
+    js_sys::JSON::parse(&json)
+        .map(JsCast::unchecked_into)
+        .map_err(|_| JsError::new("json can't be parsed back"))
+}
+
+/// Class library and dice, kept between calls.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Calculator {
+    calc: Calc,
+}
+
+/// Fallible cores of the JS methods of the same name, in plain Rust types.
+impl Calculator {
+    fn try_load_classes(&mut self, json: &str) -> Result<i32, String> {
+        match self.calc.update(json) {
+            -1 => Err("not a class file".to_string()),
+            errs => Ok(errs),
+        }
+    }
+
+    fn try_load_spells(&mut self, json: &str) -> Result<i32, String> {
+        match self.calc.update_spells(json) {
+            -1 => Err("not a spells file".to_string()),
+            errs => Ok(errs),
+        }
+    }
+
+    fn try_unit(&self, class: &str, count: i32, bonus: Stats) -> Result<Unit, String> {
+        if !self.calc.classes.contains_key(class) {
+            return Err(format!("no class `{}`", class));
+        }
+        if count < 1 {
+            return Err(format!("count must be at least 1, got {}", count));
+        }
+        Ok(Unit {
+            name: class.to_string(),
+            stats: bonus,
+            value: count,
+            initial: count,
+            ..Default::default()
+        })
+    }
+
+    fn check(&self, unit: &Unit) -> Result<(), String> {
+        match self.calc.classes.contains_key(&unit.name) {
+            true => Ok(()),
+            false => Err(format!("no class `{}`", unit.name)),
+        }
+    }
+
+    fn try_calculate(
+        &self,
+        mut attacker: Unit,
+        mut defender: Unit,
+        attack: &Attack,
+    ) -> Result<Outcome, String> {
+        self.check(&attacker)?;
+        self.check(&defender)?;
+        let strikes = self.calc.calculate(&mut defender, &mut attacker, attack);
+        Ok(Outcome {
+            strikes,
+            attacker,
+            defender,
+        })
+    }
+
+    fn try_preview(
+        &self,
+        attacker: &Unit,
+        defender: &Unit,
+        attack: &Attack,
+    ) -> Result<Preview, String> {
+        let stats = |unit: &Unit, hero: &Stats| {
+            self.calc
+                .effective_stats(unit, hero)
+                .ok_or_else(|| format!("no class `{}`", unit.name))
+        };
+        Ok(Preview {
+            retaliation: self.calc.will_retaliate(defender, attacker, attack),
+            ranged: self.calc.is_ranged(attacker, attack),
+            attacker: stats(attacker, &attack.attacker_hero)?,
+            defender: stats(defender, &attack.defender_hero)?,
+        })
+    }
+
+    fn try_simulate(
+        &self,
+        attacker: &Unit,
+        defender: &Unit,
+        attack: &Attack,
+        runs: u32,
+    ) -> Result<Simulation, String> {
+        self.check(attacker)?;
+        self.check(defender)?;
+        Ok(self.calc.simulate(defender, attacker, attack, runs))
+    }
+}
+
+fn js_err(e: String) -> JsError {
+    JsError::new(&e)
+}
+
+#[wasm_bindgen]
+impl Calculator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the class library, returns how many classes could not be read.
+    #[wasm_bindgen(js_name = loadClasses)]
+    pub fn load_classes(&mut self, json: &str) -> Result<i32, JsError> {
+        self.try_load_classes(json).map_err(js_err)
+    }
+
+    /// Replaces the spells, returns how many spells could not be read.
+    #[wasm_bindgen(js_name = loadSpells)]
+    pub fn load_spells(&mut self, json: &str) -> Result<i32, JsError> {
+        self.try_load_spells(json).map_err(js_err)
+    }
+
+    /// Makes the following rolls repeatable.
+    pub fn seed(&mut self, seed: u32) {
+        self.calc.seed(seed as u64);
+    }
+
+    pub fn classes(&self) -> Result<JsStrings, JsError> {
+        let mut names = self.calc.classes.keys().collect::<Vec<_>>();
+        names.sort();
+        to_js(&names)
+    }
+
+    /// Stats of a class as declared in the class file.
+    #[wasm_bindgen(js_name = classStats)]
+    pub fn class_stats(&self, class: &str) -> Result<JsStats, JsError> {
+        let stats = self
+            .calc
+            .classes
+            .get(class)
+            .ok_or_else(|| JsError::new(&format!("no class `{}`", class)))?;
+        to_js(stats)
+    }
+
+    /// A fresh stack of `count` creatures, at least 1, `bonus` is added to the class stats.
+    pub fn unit(&self, class: &str, count: i32, bonus: &JsValue) -> Result<JsUnit, JsError> {
+        to_js(
+            &self
+                .try_unit(class, count, from_js(bonus)?)
+                .map_err(js_err)?,
+        )
+    }
+
+    /// Rolls the attack, the passed units are left as they were.
+    pub fn calculate(
+        &self,
+        attacker: &JsUnit,
+        defender: &JsUnit,
+        attack: &JsAttack,
+    ) -> Result<JsOutcome, JsError> {
+        let outcome = self
+            .try_calculate(from_js(attacker)?, from_js(defender)?, &from_js(attack)?)
+            .map_err(js_err)?;
+        to_js(&outcome)
+    }
+
+    pub fn preview(
+        &self,
+        attacker: &JsUnit,
+        defender: &JsUnit,
+        attack: &JsAttack,
+    ) -> Result<JsPreview, JsError> {
+        let preview = self
+            .try_preview(&from_js(attacker)?, &from_js(defender)?, &from_js(attack)?)
+            .map_err(js_err)?;
+        to_js(&preview)
+    }
+
+    pub fn simulate(
+        &self,
+        attacker: &JsUnit,
+        defender: &JsUnit,
+        attack: &JsAttack,
+        runs: u32,
+    ) -> Result<JsSimulation, JsError> {
+        let simulation = self
+            .try_simulate(
+                &from_js(attacker)?,
+                &from_js(defender)?,
+                &from_js(attack)?,
+                runs,
+            )
+            .map_err(js_err)?;
+        to_js(&simulation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLASSES: &str = r#"{"Peasant": {"attack": 10, "defence": 10, "min_dmg": 1, "max_dmg": 1, "health": 10, "description": ""}}"#;
+
+    #[test]
+    fn calculate_from_json() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.try_load_classes(CLASSES), Ok(0));
+        assert!(calculator.try_load_classes("[]").is_err());
+        let peasants = calculator
+            .try_unit("Peasant", 10, Stats::default())
+            .unwrap();
+        assert!(calculator.try_unit("Angel", 1, Stats::default()).is_err());
+        for count in [0, -3] {
+            assert!(calculator
+                .try_unit("Peasant", count, Stats::default())
+                .is_err());
+        }
+        let attack: Attack = serde_json::from_str(r#"{"retaliation": true}"#).unwrap();
+        assert_eq!(attack.percent, 100);
+
+        let preview = calculator
+            .try_preview(&peasants, &peasants, &attack)
+            .unwrap();
+        assert!(preview.retaliation && !preview.ranged);
+        assert_eq!(preview.defender.health, 10);
+
+        let outcome = calculator
+            .try_calculate(peasants.clone(), peasants.clone(), &attack)
+            .unwrap();
+        assert_eq!(outcome.strikes.len(), 2);
+        assert_eq!((outcome.defender.value, outcome.attacker.value), (9, 10));
+        let json = serde_json::to_value(&outcome).unwrap();
+        assert_eq!(json["strikes"][0]["damage"], 10);
+    }
+}
@@ -1,19 +1,27 @@
-use crate::calc::{Calc, Stats, Unit};
+use crate::battlefield::{Battlefield, Hex};
+use crate::calc::{
+    Ability, Artifact, AttackMode, Calc, Cast, DamageType, Hero, Morale, Rating, Shield, Stacking,
+    Stats, StatusEffect, Strike, Unit,
+};
+use crate::encounter::{difficulty, generate, team_cost, Difficulty};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::multiplayer::{Multiplayer, SYNC_INTERVAL};
+use crate::session::{attack_log, unit_label, Session, SpectatorView, Team};
+use crate::share::SharedBattle;
 use eframe::epaint::text::TextWrapMode;
 use eframe::epaint::FontFamily;
 use egui::epaint::PathStroke;
 use egui::{
-    lerp, Align2, Color32, FontId, Rangef, RichText, Rounding, Sense, Stroke, TextStyle, Ui, Vec2,
-    Widget,
+    lerp, Align2, Color32, FontId, Pos2, Rangef, Rect, Response, RichText, Rounding, Sense, Shape,
+    Stroke, TextStyle, Ui, Vec2, Widget,
 };
 use std::mem::swap;
 use std::ops::Add;
 
 macro_rules! fancy_stat_row {
-    ( $name:literal, $field:ident, $ui:ident, $unit:ident, $self:ident) => {
-
+    ( $name:literal, $field:ident, $ui:ident, $unit:ident, $hero:ident, $self:ident) => {
         $ui.horizontal(|ui| {
-            ui.columns(6, |ui| {
+            ui.columns(8, |ui| {
                 ui[0].vertical_centered(|ui| {
                     ui.label($name);
                 });
@@ -21,6 +29,12 @@ macro_rules! fancy_stat_row {
                     ui.label("+");
                 });
                 ui[4].vertical_centered(|ui| {
+                    ui.label("+");
+                });
+                ui[5].vertical_centered(|ui| {
+                    ui.label($hero.$field.to_string()).on_hover_text("hero");
+                });
+                ui[6].vertical_centered(|ui| {
                     ui.label("=");
                 });
                 ui[3].vertical_centered(|ui| {
@@ -28,18 +42,24 @@ macro_rules! fancy_stat_row {
                         .range(if $self.negative_stats { i32::MIN } else { 0 }..=i32::MAX)
                         .ui(ui);
                 });
-                if let Some(base_stats) = $self.calc.classes.get(&$unit.name) {
+                if let (Some(base_stats), Some(stats)) = (
+                    $self.calc.classes.get(&$unit.name),
+                    $self.calc.effective_stats(&$unit, &$hero),
+                ) {
                     ui[1].vertical_centered(|ui| {
                         ui.label(base_stats.$field.to_string());
                     });
-                    ui[5].vertical_centered(|ui| {
-                        ui.label((base_stats.$field + $unit.stats.$field).to_string());
+                    ui[7].vertical_centered(|ui| {
+                        ui.label(stats.$field.to_string())
+                            .on_hover_text(sources_text(&$self.calc, &$unit, &$hero, |stats| {
+                                stats.$field
+                            }));
                     });
                 } else {
                     ui[1].vertical_centered(|ui| {
                         ui.label("-");
                     });
-                    ui[5].vertical_centered(|ui| {
+                    ui[7].vertical_centered(|ui| {
                         ui.label("-");
                     });
                 }
@@ -48,6 +68,29 @@ macro_rules! fancy_stat_row {
     };
 }
 
+macro_rules! stat_row {
+    ( $name:literal, $field:ident, $ui:ident, $unit:ident, $hero:ident, $self:ident) => {
+        $ui.horizontal(|ui| {
+            ui.columns(4, |ui| {
+                ui[0].label($name);
+                egui::DragValue::new(&mut $unit.stats.$field)
+                    .range(if $self.negative_stats { i32::MIN } else { 0 }..=i32::MAX)
+                    .ui(&mut ui[2]);
+                if let (Some(base_stats), Some(stats)) = (
+                    $self.calc.classes.get(&$unit.name),
+                    $self.calc.effective_stats(&$unit, &$hero),
+                ) {
+                    ui[1].label(base_stats.$field.to_string());
+                    ui[3].label(stats.$field.to_string());
+                } else {
+                    ui[1].label("-");
+                    ui[3].label("-");
+                }
+            });
+        });
+    };
+}
+
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct DamageCalcApp {
@@ -58,6 +101,47 @@ pub struct DamageCalcApp {
 
     json_classes: String,
     json_errs: i32,
+    #[serde(default)]
+    json_spells: String,
+    #[serde(default)]
+    spells_errs: i32,
+    #[serde(skip)]
+    json_tab: JsonTab,
+    /// Exported or pasted `Session`, not kept between runs.
+    #[serde(skip)]
+    json_session: String,
+    #[serde(skip)]
+    session_error: Option<String>,
+    /// Link made or pasted in the share settings.
+    #[serde(skip)]
+    share_link: String,
+    #[serde(default)]
+    share_library: bool,
+    /// Why a shared battle could not be opened, or a warning about it.
+    #[serde(skip)]
+    share_error: Option<String>,
+    /// Address of the web page without the fragment, empty natively.
+    #[serde(skip)]
+    page_url: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    multiplayer: Option<Multiplayer<Vec<DamageEffect>>>,
+    /// Latest view of the battle from the server, while spectating.
+    #[serde(skip)]
+    spectated: Option<SpectatorView>,
+    #[serde(default = "default_port")]
+    host_port: u16,
+    /// Server to join and the GM token, empty to spectate.
+    #[serde(default)]
+    join_address: String,
+    #[serde(default)]
+    join_token: String,
+    /// Hides what only the GM should know.
+    #[serde(default)]
+    spectator_view: bool,
+    /// Second OS window with the spectator view.
+    #[serde(default)]
+    projector: bool,
     json_window: bool,
     units_count: usize,
     settings_window: bool,
@@ -65,10 +149,68 @@ pub struct DamageCalcApp {
     class_select_search: String,
     negative_stats: bool,
     can_kill_yourself: bool,
+    #[serde(default)]
+    friendly_fire: bool,
+    #[serde(default)]
+    round: i32,
+    #[serde(default)]
+    new_effect: StatusEffect,
+    #[serde(default)]
+    new_shield: Shield,
+    #[serde(default)]
+    heal_amount: i32,
+    #[serde(default)]
+    log: Vec<String>,
+    #[serde(default)]
+    log_window: bool,
+    #[serde(default)]
+    report_window: bool,
+    #[serde(default)]
+    battlefield: Battlefield,
+    #[serde(default)]
+    battlefield_window: bool,
+    #[serde(skip)]
+    edit_obstacles: bool,
+    /// Team index and slot of the unit that moves on the battlefield.
+    #[serde(skip)]
+    field_selected: Option<(usize, usize)>,
+    #[serde(default)]
+    turns_window: bool,
+    /// Team index and slot of the unit whose turn it is.
+    #[serde(default)]
+    active: Option<(usize, usize)>,
+    #[serde(default)]
+    encounter_window: bool,
+    /// Players and enemies in the encounter builder.
+    #[serde(default)]
+    encounter_teams: (usize, usize),
+    #[serde(default = "default_runs")]
+    encounter_runs: u32,
+    #[serde(default = "default_budget")]
+    encounter_budget: i64,
+    #[serde(skip)]
+    difficulty: Option<Difficulty>,
+    #[serde(default)]
+    compare_window: bool,
+    /// Defender the damage per health of every class is measured against.
+    #[serde(default)]
+    compare_reference: String,
+    /// Text searched for in class names and descriptions.
+    #[serde(default)]
+    compare_filter: String,
+    /// Column the class comparison is sorted by, and whether it is descending.
+    #[serde(default)]
+    compare_sort: (RatingColumn, bool),
+    #[serde(skip)]
+    ratings: Vec<(String, Rating)>,
+    /// `Calc::generation`, terrain and reference defender of `ratings`.
+    #[serde(skip)]
+    ratings_for: Option<(u64, Option<String>, String)>,
 
     style: Style,
+    /// Effects in the same group are animated side by side.
     #[serde(skip)]
-    damages: Vec<DamageEffect>,
+    damages: Vec<Vec<DamageEffect>>,
 }
 
 impl Default for DamageCalcApp {
@@ -80,6 +222,23 @@ impl Default for DamageCalcApp {
             team1: 0,
             json_classes: "".to_string(),
             json_errs: -1,
+            json_spells: "".to_string(),
+            spells_errs: 0,
+            json_tab: JsonTab::Classes,
+            json_session: String::new(),
+            session_error: None,
+            share_link: String::new(),
+            share_library: true,
+            share_error: None,
+            page_url: String::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            multiplayer: None,
+            spectated: None,
+            host_port: default_port(),
+            join_address: format!("127.0.0.1:{}", default_port()),
+            join_token: String::new(),
+            spectator_view: false,
+            projector: false,
             json_window: false,
             units_count: 0,
             settings_window: false,
@@ -87,6 +246,34 @@ impl Default for DamageCalcApp {
             class_select_search: "".to_string(),
             negative_stats: false,
             can_kill_yourself: false,
+            friendly_fire: false,
+            round: 0,
+            new_effect: StatusEffect {
+                rounds: 1,
+                ..Default::default()
+            },
+            new_shield: Default::default(),
+            heal_amount: 0,
+            log: Vec::new(),
+            log_window: false,
+            report_window: false,
+            battlefield: Default::default(),
+            battlefield_window: false,
+            edit_obstacles: false,
+            field_selected: None,
+            turns_window: false,
+            active: None,
+            encounter_window: false,
+            encounter_teams: (0, 1),
+            encounter_runs: default_runs(),
+            encounter_budget: default_budget(),
+            difficulty: None,
+            compare_window: false,
+            compare_reference: String::new(),
+            compare_filter: String::new(),
+            compare_sort: (RatingColumn::Class, false),
+            ratings: Vec::new(),
+            ratings_for: None,
             style: Default::default(),
             damages: vec![],
         }
@@ -96,13 +283,278 @@ impl Default for DamageCalcApp {
 impl DamageCalcApp {
     /// Called once before the first frame.
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        let mut app = Self::default();
         if let Some(storage) = cc.storage {
             if let Some(state) = eframe::get_value::<Self>(storage, eframe::APP_KEY) {
                 state.style.apply_mono(&cc.egui_ctx);
-                return state;
+                app = state;
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let location = &cc.integration_info.web_info.location;
+            app.page_url = location.url.clone();
+            if let Some(shared) = SharedBattle::from_fragment(&location.hash) {
+                app.open_shared(shared);
+                // Reloading the page keeps the GM's changes instead of the link.
+                if let Some(window) = web_sys::window() {
+                    let _ = window.location().set_hash("");
+                }
+            }
+        }
+        app
+    }
+
+    /// Animates the oldest group of damage numbers over the whole `ui`, false once it is over.
+    fn draw_damages(&mut self, ui: &mut Ui, detailed: bool) -> bool {
+        let Some(group) = self.damages.first_mut() else {
+            return false;
+        };
+        let rect = ui.max_rect();
+        let width = rect.width() / group.len() as f32;
+        let mut playing = false;
+        for (i, effect) in group.iter_mut().enumerate() {
+            let column = Rect::from_min_size(
+                rect.min + Vec2::new(width * i as f32, 0.),
+                Vec2::new(width, rect.height()),
+            );
+            playing |= effect.render(ui, column, detailed);
+        }
+        playing
+    }
+
+    /// Same as `draw_damages`, and moves on to the next group when this one is over.
+    fn play_damages(&mut self, ui: &mut Ui, detailed: bool) {
+        if !self.draw_damages(ui, detailed) && !self.damages.is_empty() {
+            self.damages.remove(0);
+        }
+    }
+
+    /// Big overview for a second screen, the main window keeps the controls.
+    fn projector_ui(&mut self, ui: &mut Ui) {
+        self.spectator_ui(ui, 40.);
+        // While spectating the log is of this instance's own battle, not the one being watched.
+        if !self.spectating() {
+            ui.separator();
+            ui.vertical_centered(|ui| {
+                for line in self.log.iter().rev().take(5).rev() {
+                    ui.label(RichText::new(line).size(24.));
+                }
+            });
+        }
+        self.draw_damages(ui, false);
+    }
+
+    fn projector_viewport(&mut self, ctx: &egui::Context) {
+        ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("projector"),
+            egui::ViewportBuilder::default()
+                .with_title("DamageCalc battle")
+                .with_inner_size([1280.0, 720.0]),
+            |ctx, class| {
+                if class == egui::ViewportClass::Embedded {
+                    // Platforms without extra windows, like the web, get an egui window.
+                    let mut open = self.projector;
+                    egui::Window::new("projector")
+                        .open(&mut open)
+                        .show(ctx, |ui| self.projector_ui(ui));
+                    self.projector = open;
+                } else {
+                    egui::CentralPanel::default().show(ctx, |ui| self.projector_ui(ui));
+                    if ctx.input(|i| i.viewport().close_requested()) {
+                        self.projector = false;
+                    }
+                }
+            },
+        );
+    }
+
+    /// What the players may see: names, rough stack sizes and whose turn it is.
+    fn spectator_ui(&self, ui: &mut Ui, text_size: f32) {
+        let local;
+        let view = match &self.spectated {
+            Some(view) if self.spectating() => view,
+            _ => {
+                local = SpectatorView::new(
+                    &self.teams,
+                    [self.team0, self.team1],
+                    self.round,
+                    self.active,
+                );
+                &local
+            }
+        };
+        ui.vertical_centered(|ui| {
+            ui.label(RichText::new(format!("round {}", view.round)).size(text_size * 1.5));
+            if let Some((team, unit)) = &view.turn {
+                ui.label(RichText::new(format!("{}'s turn: {}", team, unit)).size(text_size));
+            }
+        });
+        ui.separator();
+        ui.columns(2, |columns| {
+            for (ui, team) in columns.iter_mut().zip(&view.teams) {
+                ui.vertical_centered(|ui| {
+                    ui.label(RichText::new(&team.name).size(text_size * 1.5).strong());
+                    if let Some(hero) = &team.hero {
+                        ui.label(RichText::new(format!("led by {}", hero)).size(text_size));
+                    }
+                    ui.separator();
+                    for unit in team.units.iter() {
+                        let mut text =
+                            RichText::new(format!("{}: {}", unit.name, unit.count)).size(text_size);
+                        if !unit.alive {
+                            text = text.strikethrough().weak();
+                        }
+                        if unit.active {
+                            text = text.strong().underline();
+                        }
+                        ui.label(text);
+                    }
+                });
+            }
+        });
+    }
+
+    /// Whether the battle comes from someone else's server and can't be changed here.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spectating(&self) -> bool {
+        self.multiplayer
+            .as_ref()
+            .is_some_and(|multiplayer| !multiplayer.is_gm())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn spectating(&self) -> bool {
+        false
+    }
+
+    /// Shows what the server sent, or sends it what spectators may see of the battle here.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn sync(&mut self, ctx: &egui::Context) {
+        let Some(multiplayer) = &mut self.multiplayer else {
+            return;
+        };
+        ctx.request_repaint_after(std::time::Duration::from_secs_f64(SYNC_INTERVAL));
+        if let Some((view, effects)) = multiplayer.receive() {
+            self.spectated = Some(view);
+            self.damages.extend(effects);
+            return;
+        }
+        let mut effects = Vec::new();
+        for group in self.damages.iter_mut() {
+            if group.iter().any(|effect| !effect.published) {
+                group.iter_mut().for_each(|effect| effect.published = true);
+                effects.push(group.iter().map(DamageEffect::for_spectators).collect());
+            }
+        }
+        let time = ctx.input(|i| i.time);
+        let Some(multiplayer) = &mut self.multiplayer else {
+            return;
+        };
+        let view = || {
+            SpectatorView::new(
+                &self.teams,
+                [self.team0, self.team1],
+                self.round,
+                self.active,
+            )
+        };
+        multiplayer.publish(time, view, effects);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn multiplayer_ui(&mut self, ui: &mut Ui) {
+        if let Some(multiplayer) = &self.multiplayer {
+            ui.label(format!(
+                "{} {}: {}",
+                if multiplayer.is_gm() {
+                    "GM of"
+                } else {
+                    "spectating"
+                },
+                multiplayer.client.addr,
+                multiplayer.status
+            ));
+            if let Some(server) = multiplayer.server() {
+                ui.label(format!(
+                    "others join this computer's address, port {}",
+                    server.addr().port()
+                ));
+                ui.horizontal(|ui| {
+                    ui.label(format!("GM token: {}", server.token()));
+                    if ui.button("copy").clicked() {
+                        ui.ctx().copy_text(server.token().to_string());
+                    }
+                });
+            }
+            if ui.button("leave").clicked() {
+                self.multiplayer = None;
+                self.spectated = None;
             }
+            return;
         }
-        Default::default()
+        ui.horizontal(|ui| {
+            egui::DragValue::new(&mut self.host_port)
+                .prefix("port ")
+                .ui(ui);
+            if ui.button("host").clicked() {
+                match Multiplayer::host(self.host_port) {
+                    Ok(multiplayer) => self.multiplayer = Some(multiplayer),
+                    Err(e) => self.share_error = Some(e),
+                }
+            }
+        });
+        ui.separator();
+        egui::TextEdit::singleline(&mut self.join_address)
+            .hint_text("address:port")
+            .ui(ui);
+        egui::TextEdit::singleline(&mut self.join_token)
+            .hint_text("GM token, empty to spectate")
+            .password(true)
+            .ui(ui);
+        if ui.button("join").clicked() {
+            let token = Some(self.join_token.trim().to_string()).filter(|t| !t.is_empty());
+            self.multiplayer = Some(Multiplayer::join(
+                self.join_address.trim().to_string(),
+                token,
+            ));
+        }
+    }
+
+    fn shared(&self) -> SharedBattle {
+        SharedBattle {
+            teams: self.teams.clone(),
+            round: self.round,
+            terrain: self.calc.terrain.clone(),
+            library: self.share_library.then(|| self.calc.library_hash()),
+        }
+    }
+
+    /// Replaces the teams with a shared battle, keeps everything as is if it can't be opened.
+    fn open_shared(&mut self, shared: Result<SharedBattle, String>) {
+        let shared = match shared.and_then(|shared| shared.check(&self.calc).map(|_| shared)) {
+            Ok(shared) => shared,
+            Err(e) => {
+                self.share_error = Some(e);
+                return;
+            }
+        };
+        self.share_error = shared.library_warning(&self.calc);
+        self.teams = shared.teams;
+        self.round = shared.round;
+        self.calc.terrain = shared.terrain;
+        self.units_count = self
+            .teams
+            .iter()
+            .map(|team| team.units.len())
+            .max()
+            .unwrap_or(0);
+        self.team0 = 0;
+        self.team1 = self.teams.len().min(2).saturating_sub(1);
+        self.active = None;
+        self.field_selected = None;
+        self.log
+            .push(format!("r{} opened a shared battle", self.round));
     }
 }
 
@@ -114,10 +566,21 @@ impl eframe::App for DamageCalcApp {
 
     /// Called each time the UI needs repainting, which may be many times per second.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.sync(ctx);
+        let sides = (self.team0, self.team1);
         let mut reset_all = false;
+        let mut settings_window = self.settings_window;
         egui::Window::new("settings")
-            .open(&mut self.settings_window)
+            .open(&mut settings_window)
             .show(ctx, |ui| {
+                // Spectators only have the spectator view, the rest of the battle is not sent.
+                if !self.spectating() {
+                    ui.checkbox(&mut self.spectator_view, "spectator view")
+                        .on_hover_text("only names and rough stack sizes, for a shared screen");
+                }
+                ui.checkbox(&mut self.projector, "projector window")
+                    .on_hover_text("a second window with a big spectator view");
                 ui.collapsing("style settings", |ui| {
                     ui.checkbox(&mut self.style.fancy_stats, "fancy stats");
                     egui::Slider::new(&mut self.style.box_size, 0.0..=100.)
@@ -150,16 +613,35 @@ impl eframe::App for DamageCalcApp {
                                 }
                                 if ui.button("+").clicked() {
                                     self.style.box_colors.push(
-                                        self.style
-                                            .box_colors
-                                            .last()
-                                            .unwrap_or(&Color32::default())
-                                            .clone(),
+                                        self.style.box_colors.last().copied().unwrap_or_default(),
                                     );
                                 }
                             });
                         });
                 });
+                ui.collapsing("share", |ui| {
+                    ui.checkbox(&mut self.share_library, "include class library hash");
+                    ui.horizontal(|ui| {
+                        if ui.button("make link").clicked() {
+                            self.share_link =
+                                format!("{}#{}", self.page_url, self.shared().to_fragment());
+                            ui.ctx().copy_text(self.share_link.clone());
+                        }
+                        if ui.button("open link").clicked() {
+                            let fragment = self.share_link.rsplit('#').next().unwrap_or_default();
+                            match SharedBattle::from_fragment(fragment) {
+                                Some(shared) => self.open_shared(shared),
+                                None => self.share_error = Some("not a battle link".to_string()),
+                            }
+                        }
+                    });
+                    egui::TextEdit::singleline(&mut self.share_link)
+                        .hint_text("paste a link to open it")
+                        .desired_width(f32::INFINITY)
+                        .ui(ui);
+                });
+                #[cfg(not(target_arch = "wasm32"))]
+                ui.collapsing("multiplayer", |ui| self.multiplayer_ui(ui));
                 ui.collapsing("real settings", |ui| {
                     egui::Label::new(RichText::new("⚠ memory loss warning ⚠").heading())
                         .wrap_mode(TextWrapMode::Extend)
@@ -167,6 +649,28 @@ impl eframe::App for DamageCalcApp {
                     ui.separator();
                     ui.checkbox(&mut self.negative_stats, "negative stats");
                     ui.checkbox(&mut self.can_kill_yourself, "can kill yourself");
+                    ui.checkbox(&mut self.friendly_fire, "friendly fire");
+                    ui.separator();
+                    egui::DragValue::new(&mut self.calc.rules.long_range_penalty)
+                        .range(0..=100)
+                        .suffix("% long range penalty")
+                        .ui(ui);
+                    egui::DragValue::new(&mut self.calc.rules.obstacle_penalty)
+                        .range(0..=100)
+                        .suffix("% obstacle penalty")
+                        .ui(ui);
+                    egui::DragValue::new(&mut self.calc.rules.melee_penalty)
+                        .range(0..=100)
+                        .suffix("% shooter melee penalty")
+                        .ui(ui);
+                    egui::DragValue::new(&mut self.calc.rules.mixed_faction_morale)
+                        .range(0..=100)
+                        .suffix("% morale per extra faction")
+                        .ui(ui);
+                    egui::DragValue::new(&mut self.calc.rules.undead_morale)
+                        .range(0..=100)
+                        .suffix("% morale next to undead")
+                        .ui(ui);
                     ui.separator();
                     if egui::DragValue::new(&mut self.units_count)
                         .range(0..=33)
@@ -195,11 +699,9 @@ impl eframe::App for DamageCalcApp {
                                         }
                                     }
                                     if ui.button("clear units stats").clicked() {
-                                        for unit in team.units.iter_mut() {
-                                            if let Some(unit) = unit {
-                                                unit.stats = Stats::default();
-                                                unit.value = 0;
-                                            }
+                                        for unit in team.units.iter_mut().flatten() {
+                                            unit.stats = Stats::default();
+                                            unit.value = 0;
                                         }
                                     }
                                     if ui.button("delete").clicked() {
@@ -218,18 +720,50 @@ impl eframe::App for DamageCalcApp {
                     }
                 });
             });
+        self.settings_window = settings_window;
         if reset_all {
             *self = DamageCalcApp::default();
         }
+        if let Some(error) = self.share_error.clone() {
+            let mut open = true;
+            egui::Window::new("shared battle")
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    ui.colored_label(Color32::RED, error);
+                });
+            if !open {
+                self.share_error = None;
+            }
+        }
+        if self.projector {
+            self.projector_viewport(ctx);
+        }
+        if self.spectator_view || self.spectating() {
+            egui::TopBottomPanel::top("menu").show(ctx, |ui| {
+                if ui.button("settings").clicked() {
+                    self.settings_window = !self.settings_window;
+                }
+            });
+            egui::CentralPanel::default().show(ctx, |ui| {
+                self.spectator_ui(ui, 24.);
+                self.play_damages(ui, false);
+            });
+            return;
+        }
+        let mut json_window = self.json_window;
         egui::Window::new("json editor")
-            .open(&mut self.json_window)
+            .open(&mut json_window)
             .max_height(ctx.available_rect().height() - 100.)
             .show(ctx, |ui| {
                 ui.horizontal(|ui| {
                     if ui.button("apply").clicked() {
                         self.json_errs = self.calc.update(&self.json_classes);
+                        self.spells_errs = self.calc.update_spells(&self.json_spells);
                     }
-                    ui.label(format!("errors: {}", self.json_errs));
+                    ui.label(format!(
+                        "errors: {}, spells: {}",
+                        self.json_errs, self.spells_errs
+                    ));
                 });
                 ui.separator();
                 ui.label("invalid units:");
@@ -271,12 +805,128 @@ impl eframe::App for DamageCalcApp {
                 egui::ScrollArea::vertical()
                     .id_source("json_editor")
                     .show(ui, |ui| {
-                        egui::TextEdit::multiline(&mut self.json_classes)
-                            .code_editor()
-                            .desired_width(f32::INFINITY)
-                            .show(ui);
+                        ui.horizontal(|ui| {
+                            ui.selectable_value(&mut self.json_tab, JsonTab::Classes, "classes");
+                            ui.selectable_value(&mut self.json_tab, JsonTab::Spells, "spells");
+                            ui.selectable_value(&mut self.json_tab, JsonTab::Session, "session");
+                        });
+                        if self.json_tab == JsonTab::Session {
+                            ui.horizontal(|ui| {
+                                if ui.button("export").clicked() {
+                                    self.json_session = self.session().to_json();
+                                    self.session_error = None;
+                                }
+                                if ui.button("import").clicked() {
+                                    match Session::from_json(&self.json_session) {
+                                        Ok(session) => {
+                                            self.load_session(session);
+                                            self.session_error = None;
+                                        }
+                                        Err(e) => self.session_error = Some(e),
+                                    }
+                                }
+                                if let Some(e) = &self.session_error {
+                                    ui.colored_label(Color32::RED, e);
+                                }
+                            });
+                        }
+                        egui::TextEdit::multiline(match self.json_tab {
+                            JsonTab::Classes => &mut self.json_classes,
+                            JsonTab::Spells => &mut self.json_spells,
+                            JsonTab::Session => &mut self.json_session,
+                        })
+                        .code_editor()
+                        .desired_width(f32::INFINITY)
+                        .show(ui);
+                    });
+            });
+        self.json_window = json_window;
+        egui::Window::new("log")
+            .open(&mut self.log_window)
+            .show(ctx, |ui| {
+                if ui.button("clear").clicked() {
+                    self.log.clear();
+                }
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for line in self.log.iter() {
+                            ui.label(line);
+                        }
                     });
             });
+        egui::Window::new("casualties")
+            .open(&mut self.report_window)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (team_i, team) in self.teams.iter_mut().enumerate() {
+                        ui.heading(&team.name);
+                        egui::Grid::new(format!("{}_casualties", team_i))
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for header in ["unit", "alive", "kills", "losses", "dealt", "taken"]
+                                {
+                                    ui.label(header);
+                                }
+                                ui.end_row();
+                                for (slot, unit) in team.units.iter().enumerate() {
+                                    if let Some(unit) = unit {
+                                        ui.label(unit_label(&team.name, slot, unit));
+                                        ui.label(format!("{}/{}", unit.value, unit.initial));
+                                        ui.label(unit.kills.to_string());
+                                        ui.label(unit.losses.to_string());
+                                        ui.label(unit.damage_dealt.to_string());
+                                        ui.label(unit.damage_taken.to_string());
+                                        ui.end_row();
+                                    }
+                                }
+                                let units = || team.units.iter().flatten();
+                                ui.label("total");
+                                ui.label(format!(
+                                    "{}/{}",
+                                    units().map(|u| u.value).sum::<i32>(),
+                                    units().map(|u| u.initial).sum::<i32>()
+                                ));
+                                ui.label(units().map(|u| u.kills).sum::<i32>().to_string());
+                                ui.label(units().map(|u| u.losses).sum::<i32>().to_string());
+                                ui.label(units().map(|u| u.damage_dealt).sum::<i32>().to_string());
+                                ui.label(units().map(|u| u.damage_taken).sum::<i32>().to_string());
+                                ui.end_row();
+                            });
+                        if ui
+                            .button("new battle")
+                            .on_hover_text("reset counters and take current counts as initial")
+                            .clicked()
+                        {
+                            for unit in team.units.iter_mut().flatten() {
+                                unit.reset_casualties();
+                            }
+                        }
+                        ui.separator();
+                    }
+                });
+            });
+        let mut battlefield_window = self.battlefield_window;
+        egui::Window::new("battlefield")
+            .open(&mut battlefield_window)
+            .show(ctx, |ui| self.battlefield_ui(ui));
+        self.battlefield_window = battlefield_window;
+        let mut turns_window = self.turns_window;
+        egui::Window::new("turns")
+            .open(&mut turns_window)
+            .show(ctx, |ui| self.turns_ui(ui));
+        self.turns_window = turns_window;
+        let mut encounter_window = self.encounter_window;
+        egui::Window::new("encounter")
+            .open(&mut encounter_window)
+            .show(ctx, |ui| self.encounter_ui(ui));
+        self.encounter_window = encounter_window;
+        let mut compare_window = self.compare_window;
+        egui::Window::new("compare classes")
+            .open(&mut compare_window)
+            .show(ctx, |ui| self.compare_ui(ui));
+        self.compare_window = compare_window;
         egui::Window::new("class select")
             .open(&mut self.class_select_window)
             .show(ctx, |ui| {
@@ -300,9 +950,7 @@ impl eframe::App for DamageCalcApp {
                                 .unwrap_or(None)
                                 .unwrap_or(Unit {
                                     name: "-".to_string(),
-                                    stats: Default::default(),
-                                    value: 0,
-                                    damage_left: 0,
+                                    ..Default::default()
                                 })
                                 .name
                         ))
@@ -315,22 +963,17 @@ impl eframe::App for DamageCalcApp {
                                     if name
                                         .to_lowercase()
                                         .contains(&self.class_select_search.to_lowercase())
+                                        && ui.button(name).clicked()
                                     {
-                                        if ui.button(name).clicked() {
-                                            unit.name = name.clone();
-                                        }
+                                        unit.name = name.clone();
                                     }
                                 }
                             });
-                        } else {
-                            if ui.button("alive").clicked() {
-                                *maybe_unit = Some(Unit {
-                                    name: "".to_string(),
-                                    stats: Default::default(),
-                                    value: 0,
-                                    damage_left: 0,
-                                });
-                            }
+                        } else if ui.button("alive").clicked() {
+                            *maybe_unit = Some(Unit {
+                                name: "".to_string(),
+                                ..Default::default()
+                            });
                         }
                     }
                 }
@@ -353,8 +996,48 @@ impl eframe::App for DamageCalcApp {
                 if ui.button("class select").clicked() {
                     self.class_select_window = !self.class_select_window;
                 }
+                if ui.button("log").clicked() {
+                    self.log_window = !self.log_window;
+                }
+                if ui.button("casualties").clicked() {
+                    self.report_window = !self.report_window;
+                }
+                if ui.button("battlefield").clicked() {
+                    self.battlefield_window = !self.battlefield_window;
+                }
+                if ui.button("turns").clicked() {
+                    self.turns_window = !self.turns_window;
+                }
+                if ui.button("encounter").clicked() {
+                    self.encounter_window = !self.encounter_window;
+                }
+                if ui.button("compare").clicked() {
+                    self.compare_window = !self.compare_window;
+                }
+                ui.separator();
+                ui.label(format!("round {}", self.round));
+                if ui.button("next round").clicked() {
+                    self.round += 1;
+                    self.active = None;
+                    for team in self.teams.iter_mut() {
+                        team.new_round();
+                    }
+                }
+                ui.separator();
+                egui::ComboBox::from_id_source("terrain")
+                    .selected_text(self.calc.terrain.as_deref().unwrap_or("no terrain"))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.calc.terrain, None, "no terrain");
+                        for terrain in self.calc.terrains() {
+                            ui.selectable_value(
+                                &mut self.calc.terrain,
+                                Some(terrain.clone()),
+                                terrain,
+                            );
+                        }
+                    });
                 //if ui.button("test").clicked() {
-                //    self.damages.push(DamageEffect::new(100,["line1".to_string(),"line2".to_string()]))
+                //    self.damages.push(vec![DamageEffect::new(Strike { damage: 100, text: ["line1".to_string(), "line2".to_string()], ..Default::default() })])
                 //}
             });
         });
@@ -372,6 +1055,8 @@ impl eframe::App for DamageCalcApp {
                     egui::ScrollArea::vertical().show(ui, |ui| {
                         if let Some(sel) = DamageCalcApp::select_column(ui, team, &self.style) {
                             team.select = sel;
+
+                            self.field_selected = Some((self.team0, sel));
                         }
                     });
                 } else {
@@ -392,6 +1077,8 @@ impl eframe::App for DamageCalcApp {
                     egui::ScrollArea::vertical().show(ui, |ui| {
                         if let Some(sel) = DamageCalcApp::select_column(ui, team, &self.style) {
                             team.select = sel;
+
+                            self.field_selected = Some((self.team1, sel));
                         }
                     });
                 } else {
@@ -412,6 +1099,8 @@ impl eframe::App for DamageCalcApp {
                                    team: &mut Team,
                                    team_num: usize,
                                    enemy_team: &mut Team| {
+                DamageCalcApp::hero_ui(ui, &mut team.hero, team_num);
+                let hero = team.hero_bonus();
                 if let Some(unit_cell) = team.units.get_mut(team.select) {
                     if let Some(unit) = unit_cell {
                         ui.horizontal(|ui| {
@@ -428,20 +1117,25 @@ impl eframe::App for DamageCalcApp {
                                 });
                         });
                         if self.style.fancy_stats {
-                            fancy_stat_row!("atk:", attack, ui, unit, self);
-                            fancy_stat_row!("def:", defense, ui, unit, self);
-                            fancy_stat_row!("hp:", health, ui, unit, self);
-                            fancy_stat_row!("dmg_:", min_dmg, ui, unit, self);
-                            fancy_stat_row!("dmg[]:", max_dmg, ui, unit, self);
-                            fancy_stat_row!("luck:", luck, ui, unit, self);
-                            fancy_stat_row!("lead:", leadership, ui, unit, self);
-                            fancy_stat_row!("abs:", absorb, ui, unit, self);
+                            fancy_stat_row!("atk:", attack, ui, unit, hero, self);
+                            fancy_stat_row!("def:", defense, ui, unit, hero, self);
+                            fancy_stat_row!("hp:", health, ui, unit, hero, self);
+                            fancy_stat_row!("dmg_:", min_dmg, ui, unit, hero, self);
+                            fancy_stat_row!("dmg[]:", max_dmg, ui, unit, hero, self);
+                            fancy_stat_row!("luck:", luck, ui, unit, hero, self);
+                            fancy_stat_row!("lead:", leadership, ui, unit, hero, self);
+                            fancy_stat_row!("mor:", morale, ui, unit, hero, self);
+                            fancy_stat_row!("spd:", speed, ui, unit, hero, self);
                             ui.horizontal(|ui| {
                                 ui.columns(3, |ui| {
-                                    egui::DragValue::new(&mut unit.value)
+                                    if egui::DragValue::new(&mut unit.value)
                                         .range(0..=i32::MAX)
                                         .suffix(" count")
-                                        .ui(&mut ui[0]);
+                                        .ui(&mut ui[0])
+                                        .changed()
+                                    {
+                                        unit.initial = unit.value;
+                                    }
                                     egui::DragValue::new(&mut team.percent)
                                         .range(0..=i32::MAX)
                                         .suffix("%")
@@ -449,42 +1143,65 @@ impl eframe::App for DamageCalcApp {
                                     ui[2].checkbox(&mut team.retaliation, "retaliation")
                                 });
                             });
+                            DamageCalcApp::attack_mode_ui(
+                                ui,
+                                &mut team.mode,
+                                &mut team.long_range,
+                                &mut team.obstacle,
+                                team_num,
+                                self.calc.has_ability(unit, &Ability::Shooter),
+                            );
                             ui.horizontal(|ui| {
                                 if ui.button("R").clicked() {
                                     unit.damage_left = 0;
                                 }
-                                if let Some(base_stats) = self.calc.classes.get(&unit.name) {
-                                    egui::ProgressBar::new(
-                                        1. - unit.damage_left as f32
-                                            / (base_stats.health + unit.stats.health) as f32,
-                                    )
-                                    .text(format!(
-                                        "{}/{}",
-                                        base_stats.health + unit.stats.health - unit.damage_left,
-                                        base_stats.health + unit.stats.health
-                                    ))
-                                    .ui(ui);
-                                } else {
-                                    egui::ProgressBar::new(1.).text("-").ui(ui);
-                                }
+                                DamageCalcApp::hp_bar(
+                                    ui,
+                                    unit,
+                                    self.calc.effective_stats(unit, &hero),
+                                );
                             });
                             ui.vertical_centered_justified(|ui| {
+                                let mut attack = team.attack(enemy_team);
                                 match (
                                     team.units.get_mut(team.select),
                                     enemy_team.units.get_mut(enemy_team.select),
                                 ) {
                                     (Some(Some(unit)), Some(Some(enemy_unit))) => {
-                                        if ui.button("attack").clicked() {
-                                            let (dmg, self_dmg, text) = self.calc.calculate(
-                                                enemy_unit,
-                                                unit,
-                                                team.percent,
-                                                enemy_team.retaliation,
+                                        let check = self.battlefield.check_attack(
+                                            unit.position,
+                                            enemy_unit.position,
+                                            self.calc.is_ranged(unit, &attack),
+                                            &mut attack,
+                                        );
+                                        if ui
+                                            .add_enabled(
+                                                check.is_ok(),
+                                                egui::Button::new(attack_label(
+                                                    self.calc
+                                                        .will_retaliate(enemy_unit, unit, &attack),
+                                                )),
+                                            )
+                                            .on_disabled_hover_text(check.err().unwrap_or_default())
+                                            .clicked()
+                                        {
+                                            let strikes =
+                                                self.calc.calculate(enemy_unit, unit, &attack);
+                                            self.log.push(attack_log(
+                                                self.round,
+                                                &unit_label(&team.name, team.select, unit),
+                                                &unit_label(
+                                                    &enemy_team.name,
+                                                    enemy_team.select,
+                                                    enemy_unit,
+                                                ),
+                                                &strikes,
+                                            ));
+                                            self.damages.extend(
+                                                strikes
+                                                    .into_iter()
+                                                    .map(|strike| vec![DamageEffect::new(strike)]),
                                             );
-                                            self.damages.push(DamageEffect::new(dmg, text));
-                                            if let Some((dmg, text)) = self_dmg {
-                                                self.damages.push(DamageEffect::new(dmg, text));
-                                            }
                                         }
                                     }
                                     _ => {
@@ -514,9 +1231,7 @@ impl eframe::App for DamageCalcApp {
                                                     .unwrap_or(None)
                                                     .unwrap_or(Unit {
                                                         name: "-".to_string(),
-                                                        stats: Default::default(),
-                                                        value: 0,
-                                                        damage_left: 0,
+                                                        ..Default::default()
                                                     })
                                                     .name
                                             ))
@@ -524,184 +1239,60 @@ impl eframe::App for DamageCalcApp {
                                         if ui[0].button("attack yourself").clicked() {
                                             let mut u1 = team.units.clone();
                                             let mut u2 = team.units.clone();
-                                            match (
+                                            if let (Some(Some(unit)), Some(Some(enemy_unit))) = (
                                                 u1.get_mut(team.select),
                                                 u2.get_mut(team.second_select),
                                             ) {
-                                                (Some(Some(unit)), Some(Some(enemy_unit))) => {
-                                                    let (dmg, self_dmg, text) =
-                                                        self.calc.calculate(
-                                                            enemy_unit,
-                                                            unit,
-                                                            team.percent,
-                                                            team.retaliation,
-                                                        );
-                                                    *team.units.get_mut(team.select).unwrap() =
-                                                        Some(unit.clone());
-                                                    *team
-                                                        .units
-                                                        .get_mut(team.second_select)
-                                                        .unwrap() = Some(enemy_unit.clone());
-                                                    self.damages.push(DamageEffect::new(dmg, text));
-                                                    if let Some((dmg, text)) = self_dmg {
-                                                        self.damages
-                                                            .push(DamageEffect::new(dmg, text));
-                                                    }
-                                                }
-                                                _ => {}
+                                                let strikes = self.calc.calculate(
+                                                    enemy_unit,
+                                                    unit,
+                                                    &team.attack(team),
+                                                );
+                                                self.log.push(attack_log(
+                                                    self.round,
+                                                    &unit_label(&team.name, team.select, unit),
+                                                    &unit_label(
+                                                        &team.name,
+                                                        team.second_select,
+                                                        enemy_unit,
+                                                    ),
+                                                    &strikes,
+                                                ));
+                                                *team.units.get_mut(team.select).unwrap() =
+                                                    Some(unit.clone());
+                                                *team.units.get_mut(team.second_select).unwrap() =
+                                                    Some(enemy_unit.clone());
+                                                self.damages.extend(
+                                                    strikes.into_iter().map(|strike| {
+                                                        vec![DamageEffect::new(strike)]
+                                                    }),
+                                                );
                                             }
                                         }
                                     });
                                 }
                             });
                         } else {
+                            stat_row!("atk:", attack, ui, unit, hero, self);
+                            stat_row!("def:", defense, ui, unit, hero, self);
+                            stat_row!("hp:", health, ui, unit, hero, self);
+                            stat_row!("dmg_:", min_dmg, ui, unit, hero, self);
+                            stat_row!("dmg[]:", max_dmg, ui, unit, hero, self);
+                            stat_row!("luck:", luck, ui, unit, hero, self);
+                            stat_row!("lead:", leadership, ui, unit, hero, self);
+                            stat_row!("mor:", morale, ui, unit, hero, self);
+                            stat_row!("spd:", speed, ui, unit, hero, self);
                             ui.horizontal(|ui| {
                                 ui.columns(4, |ui| {
-                                    ui[0].label("atk:");
-                                    egui::DragValue::new(&mut unit.stats.attack)
-                                        .range(
-                                            if self.negative_stats { i32::MIN } else { 0 }
-                                                ..=i32::MAX,
-                                        )
-                                        .ui(&mut ui[2]);
-                                    if let Some(base_stats) = self.calc.classes.get(&unit.name) {
-                                        ui[1].label(base_stats.attack.to_string());
-                                        ui[3].label(
-                                            (base_stats.attack + unit.stats.attack).to_string(),
-                                        );
-                                    } else {
-                                        ui[1].label("-");
-                                        ui[3].label("-");
-                                    }
-                                });
-                            });
-                            ui.horizontal(|ui| {
-                                ui.columns(4, |ui| {
-                                    ui[0].label("def:");
-                                    egui::DragValue::new(&mut unit.stats.defense)
-                                        .range(
-                                            if self.negative_stats { i32::MIN } else { 0 }
-                                                ..=i32::MAX,
-                                        )
-                                        .ui(&mut ui[2]);
-                                    if let Some(base_stats) = self.calc.classes.get(&unit.name) {
-                                        ui[1].label(base_stats.defense.to_string());
-                                        ui[3].label(
-                                            (base_stats.defense + unit.stats.defense).to_string(),
-                                        );
-                                    } else {
-                                        ui[1].label("-");
-                                        ui[3].label("-");
-                                    }
-                                });
-                            });
-                            ui.horizontal(|ui| {
-                                ui.columns(4, |ui| {
-                                    ui[0].label("hp:");
-                                    egui::DragValue::new(&mut unit.stats.health)
-                                        .range(
-                                            if self.negative_stats { i32::MIN } else { 0 }
-                                                ..=i32::MAX,
-                                        )
-                                        .ui(&mut ui[2]);
-                                    if let Some(base_stats) = self.calc.classes.get(&unit.name) {
-                                        ui[1].label(base_stats.health.to_string());
-                                        ui[3].label(
-                                            (base_stats.health + unit.stats.health).to_string(),
-                                        );
-                                    } else {
-                                        ui[1].label("-");
-                                        ui[3].label("-");
-                                    }
-                                });
-                            });
-                            ui.horizontal(|ui| {
-                                ui.columns(4, |ui| {
-                                    ui[0].label("dmg_:");
-                                    egui::DragValue::new(&mut unit.stats.min_dmg)
-                                        .range(
-                                            if self.negative_stats { i32::MIN } else { 0 }
-                                                ..=i32::MAX,
-                                        )
-                                        .ui(&mut ui[2]);
-                                    if let Some(base_stats) = self.calc.classes.get(&unit.name) {
-                                        ui[1].label(base_stats.min_dmg.to_string());
-                                        ui[3].label(
-                                            (base_stats.min_dmg + unit.stats.min_dmg).to_string(),
-                                        );
-                                    } else {
-                                        ui[1].label("-");
-                                        ui[3].label("-");
-                                    }
-                                });
-                            });
-                            ui.horizontal(|ui| {
-                                ui.columns(4, |ui| {
-                                    ui[0].label("dmg[]:");
-                                    egui::DragValue::new(&mut unit.stats.max_dmg)
-                                        .range(
-                                            if self.negative_stats { i32::MIN } else { 0 }
-                                                ..=i32::MAX,
-                                        )
-                                        .ui(&mut ui[2]);
-                                    if let Some(base_stats) = self.calc.classes.get(&unit.name) {
-                                        ui[1].label(base_stats.max_dmg.to_string());
-                                        ui[3].label(
-                                            (base_stats.max_dmg + unit.stats.max_dmg).to_string(),
-                                        );
-                                    } else {
-                                        ui[1].label("-");
-                                        ui[3].label("-");
-                                    }
-                                });
-                            });
-                            ui.horizontal(|ui| {
-                                ui.columns(4, |ui| {
-                                    ui[0].label("luck:");
-                                    egui::DragValue::new(&mut unit.stats.luck)
-                                        .range(
-                                            if self.negative_stats { i32::MIN } else { 0 }
-                                                ..=i32::MAX,
-                                        )
-                                        .ui(&mut ui[2]);
-                                    if let Some(base_stats) = self.calc.classes.get(&unit.name) {
-                                        ui[1].label(base_stats.luck.to_string());
-                                        ui[3]
-                                            .label((base_stats.luck + unit.stats.luck).to_string());
-                                    } else {
-                                        ui[1].label("-");
-                                        ui[3].label("-");
-                                    }
-                                });
-                            });
-                            ui.horizontal(|ui| {
-                                ui.columns(4, |ui| {
-                                    ui[0].label("lead:");
-                                    egui::DragValue::new(&mut unit.stats.leadership)
-                                        .range(
-                                            if self.negative_stats { i32::MIN } else { 0 }
-                                                ..=i32::MAX,
-                                        )
-                                        .ui(&mut ui[2]);
-                                    if let Some(base_stats) = self.calc.classes.get(&unit.name) {
-                                        ui[1].label(base_stats.leadership.to_string());
-                                        ui[3].label(
-                                            (base_stats.leadership + unit.stats.leadership)
-                                                .to_string(),
-                                        );
-                                    } else {
-                                        ui[1].label("-");
-                                        ui[3].label("-");
-                                    }
-                                });
-                            });
-                            ui.horizontal(|ui| {
-                                ui.columns(4, |ui| {
-                                    egui::DragValue::new(&mut unit.value)
+                                    if egui::DragValue::new(&mut unit.value)
                                         .range(0..=i32::MAX)
                                         .clamp_to_range(false)
                                         .suffix(" count")
-                                        .ui(&mut ui[0]);
+                                        .ui(&mut ui[0])
+                                        .changed()
+                                    {
+                                        unit.initial = unit.value;
+                                    }
                                     egui::DragValue::new(&mut team.percent)
                                         .range(0..=i32::MAX)
                                         .clamp_to_range(false)
@@ -710,43 +1301,69 @@ impl eframe::App for DamageCalcApp {
                                     ui[2].checkbox(&mut team.retaliation, "retaliation")
                                 });
                             });
+                            DamageCalcApp::attack_mode_ui(
+                                ui,
+                                &mut team.mode,
+                                &mut team.long_range,
+                                &mut team.obstacle,
+                                team_num,
+                                self.calc.has_ability(unit, &Ability::Shooter),
+                            );
                             ui.horizontal(|ui| {
                                 if ui.button("R").clicked() {
                                     unit.damage_left = 0;
                                 }
-                                if let Some(base_stats) = self.calc.classes.get(&unit.name) {
-                                    egui::ProgressBar::new(
-                                        1. - unit.damage_left as f32
-                                            / (base_stats.health + unit.stats.health) as f32,
-                                    )
-                                    .text(format!(
-                                        "{}/{}",
-                                        base_stats.health + unit.stats.health - unit.damage_left,
-                                        base_stats.health + unit.stats.health
-                                    ))
-                                    .ui(ui);
-                                } else {
-                                    egui::ProgressBar::new(1.).text("-").ui(ui);
-                                }
+                                DamageCalcApp::hp_bar(
+                                    ui,
+                                    unit,
+                                    self.calc.effective_stats(unit, &hero),
+                                );
                             });
                             ui.horizontal(|ui| {
                                 ui.columns(2, |ui| {
+                                    let mut attack = team.attack(enemy_team);
                                     match (
                                         team.units.get_mut(team.select),
                                         enemy_team.units.get_mut(enemy_team.select),
                                     ) {
                                         (Some(Some(unit)), Some(Some(enemy_unit))) => {
-                                            if ui[0].button("attack").clicked() {
-                                                let (dmg, self_dmg, text) = self.calc.calculate(
-                                                    enemy_unit,
-                                                    unit,
-                                                    team.percent,
-                                                    enemy_team.retaliation,
+                                            let check = self.battlefield.check_attack(
+                                                unit.position,
+                                                enemy_unit.position,
+                                                self.calc.is_ranged(unit, &attack),
+                                                &mut attack,
+                                            );
+                                            if ui[0]
+                                                .add_enabled(
+                                                    check.is_ok(),
+                                                    egui::Button::new(attack_label(
+                                                        self.calc.will_retaliate(
+                                                            enemy_unit, unit, &attack,
+                                                        ),
+                                                    )),
+                                                )
+                                                .on_disabled_hover_text(
+                                                    check.err().unwrap_or_default(),
+                                                )
+                                                .clicked()
+                                            {
+                                                let strikes =
+                                                    self.calc.calculate(enemy_unit, unit, &attack);
+                                                self.log.push(attack_log(
+                                                    self.round,
+                                                    &unit_label(&team.name, team.select, unit),
+                                                    &unit_label(
+                                                        &enemy_team.name,
+                                                        enemy_team.select,
+                                                        enemy_unit,
+                                                    ),
+                                                    &strikes,
+                                                ));
+                                                self.damages.extend(
+                                                    strikes.into_iter().map(|strike| {
+                                                        vec![DamageEffect::new(strike)]
+                                                    }),
                                                 );
-                                                self.damages.push(DamageEffect::new(dmg, text));
-                                                if let Some((dmg, text)) = self_dmg {
-                                                    self.damages.push(DamageEffect::new(dmg, text));
-                                                }
                                             }
                                         }
                                         _ => {
@@ -779,9 +1396,7 @@ impl eframe::App for DamageCalcApp {
                                                     .unwrap_or(None)
                                                     .unwrap_or(Unit {
                                                         name: "-".to_string(),
-                                                        stats: Default::default(),
-                                                        value: 0,
-                                                        damage_left: 0,
+                                                        ..Default::default()
                                                     })
                                                     .name
                                             ))
@@ -789,63 +1404,319 @@ impl eframe::App for DamageCalcApp {
                                         if ui[0].button("attack yourself").clicked() {
                                             let mut u1 = team.units.clone();
                                             let mut u2 = team.units.clone();
-                                            match (
+                                            if let (Some(Some(unit)), Some(Some(enemy_unit))) = (
                                                 u1.get_mut(team.select),
                                                 u2.get_mut(team.second_select),
                                             ) {
-                                                (Some(Some(unit)), Some(Some(enemy_unit))) => {
-                                                    let (dmg, self_dmg, text) =
-                                                        self.calc.calculate(
-                                                            enemy_unit,
-                                                            unit,
-                                                            team.percent,
-                                                            team.retaliation,
-                                                        );
-                                                    *team.units.get_mut(team.select).unwrap() =
-                                                        Some(unit.clone());
-                                                    *team
-                                                        .units
-                                                        .get_mut(team.second_select)
-                                                        .unwrap() = Some(enemy_unit.clone());
-                                                    self.damages.push(DamageEffect::new(dmg, text));
-                                                    if let Some((dmg, text)) = self_dmg {
-                                                        self.damages
-                                                            .push(DamageEffect::new(dmg, text));
-                                                    }
-                                                }
-                                                _ => {}
+                                                let strikes = self.calc.calculate(
+                                                    enemy_unit,
+                                                    unit,
+                                                    &team.attack(team),
+                                                );
+                                                self.log.push(attack_log(
+                                                    self.round,
+                                                    &unit_label(&team.name, team.select, unit),
+                                                    &unit_label(
+                                                        &team.name,
+                                                        team.second_select,
+                                                        enemy_unit,
+                                                    ),
+                                                    &strikes,
+                                                ));
+                                                *team.units.get_mut(team.select).unwrap() =
+                                                    Some(unit.clone());
+                                                *team.units.get_mut(team.second_select).unwrap() =
+                                                    Some(enemy_unit.clone());
+                                                self.damages.extend(
+                                                    strikes.into_iter().map(|strike| {
+                                                        vec![DamageEffect::new(strike)]
+                                                    }),
+                                                );
                                             }
                                         }
                                     });
                                 });
                             }
                         }
-                        if let Some(unit_cell) = team.units.get_mut(team.select) {
-                            if let Some(unit) = unit_cell {
-                                if let Some(base) = self.calc.classes.get(&unit.name) {
-                                    ui.label(&base.desc);
-                                }
-                            }
-                        }
-                    } else {
-                        ui.horizontal(|ui| {
-                            let mut new_class = None;
-                            ui.add_space((team0_panel.width() - ui.spacing().combo_width) / 2.);
-                            egui::ComboBox::from_id_source(format!("{}_unit_class", team_num))
-                                .selected_text("💀")
-                                .show_ui(ui, |ui| {
-                                    for class in self.calc.classes.keys() {
-                                        ui.selectable_value(&mut new_class, Some(class), class);
-                                    }
-                                });
-                            if let Some(class) = new_class {
-                                *unit_cell = Some(Unit {
-                                    name: class.clone(),
-                                    stats: Default::default(),
-                                    value: 0,
-                                    damage_left: 0,
-                                });
-                            }
+                        if let Some(Some(unit)) = team.units.get_mut(team.select) {
+                            ui.horizontal(|ui| {
+                                ui.columns(3, |ui| {
+                                    egui::ComboBox::from_id_source(format!("{}_spell", team_num))
+                                        .selected_text(&team.spell)
+                                        .width(ui[0].available_width())
+                                        .show_ui(&mut ui[0], |ui| {
+                                            for (name, spell) in self.calc.spells.iter() {
+                                                ui.selectable_value(
+                                                    &mut team.spell,
+                                                    name.clone(),
+                                                    name,
+                                                )
+                                                .on_hover_text(&spell.desc);
+                                            }
+                                        });
+                                    egui::DragValue::new(&mut team.spell_power)
+                                        .range(0..=i32::MAX)
+                                        .suffix(" power")
+                                        .ui(&mut ui[1]);
+                                    let spell = self.calc.spells.get(&team.spell);
+                                    let enemy_hero = enemy_team.hero_bonus();
+                                    let target = match spell {
+                                        Some(spell) if spell.friendly() => {
+                                            Some((&mut *unit, &team.name, team.select, &hero))
+                                        }
+                                        Some(_) => {
+                                            match enemy_team.units.get_mut(enemy_team.select) {
+                                                Some(Some(enemy_unit)) => Some((
+                                                    enemy_unit,
+                                                    &enemy_team.name,
+                                                    enemy_team.select,
+                                                    &enemy_hero,
+                                                )),
+                                                _ => None,
+                                            }
+                                        }
+                                        None => None,
+                                    };
+                                    match (spell, target) {
+                                        (Some(spell), Some((target, team_name, slot, hero))) => {
+                                            if ui[2].button("cast").clicked() {
+                                                let cast = self.calc.cast(
+                                                    spell,
+                                                    &team.spell,
+                                                    team.spell_power,
+                                                    target,
+                                                    hero,
+                                                );
+                                                let target = unit_label(team_name, slot, target);
+                                                self.log.push(cast_log(
+                                                    self.round,
+                                                    &team.spell,
+                                                    &target,
+                                                    &cast,
+                                                ));
+                                                match cast {
+                                                    Cast::Damage(strike) => self
+                                                        .damages
+                                                        .push(vec![DamageEffect::new(strike)]),
+                                                    Cast::Heal(healed, revived) => {
+                                                        self.damages.push(vec![DamageEffect::heal(
+                                                            healed, revived,
+                                                        )])
+                                                    }
+                                                    Cast::Effect(_) | Cast::Resisted => {}
+                                                }
+                                            }
+                                        }
+                                        _ => {
+                                            ui[2].add_enabled(false, egui::Button::new("cast"));
+                                        }
+                                    }
+                                });
+                            });
+                            ui.horizontal(|ui| {
+                                ui.columns(3, |ui| {
+                                    egui::DragValue::new(&mut self.heal_amount)
+                                        .range(0..=i32::MAX)
+                                        .suffix(" hp")
+                                        .ui(&mut ui[0]);
+                                    for (i, (label, resurrect)) in
+                                        [("heal", false), ("resurrect", true)]
+                                            .into_iter()
+                                            .enumerate()
+                                    {
+                                        if ui[i + 1].button(label).clicked() {
+                                            let value = unit.value;
+                                            let healed = self.calc.heal(
+                                                unit,
+                                                &hero,
+                                                self.heal_amount,
+                                                resurrect,
+                                            );
+                                            let revived = unit.value - value;
+                                            self.log.push(format!(
+                                                "r{} {}: {} {} hp, {} revived",
+                                                self.round,
+                                                unit_label(&team.name, team.select, unit),
+                                                label,
+                                                healed,
+                                                revived
+                                            ));
+                                            self.damages
+                                                .push(vec![DamageEffect::heal(healed, revived)]);
+                                        }
+                                    }
+                                });
+                            });
+                            DamageCalcApp::effects_ui(ui, unit, team_num, &mut self.new_effect);
+                            DamageCalcApp::shields_ui(ui, unit, team_num, &mut self.new_shield);
+                            if let Some(base) = self.calc.classes.get(&unit.name) {
+                                ui.label(&base.desc);
+                            }
+                            let abilities = self
+                                .calc
+                                .abilities(unit)
+                                .map(|ability| format!("{ability:?}"))
+                                .collect::<Vec<_>>();
+                            if !abilities.is_empty() {
+                                ui.label(abilities.join(", "));
+                            }
+                        }
+                        ui.collapsing("area attack", |ui| {
+                            ui.horizontal_wrapped(|ui| {
+                                for (slot, enemy) in enemy_team.units.iter().enumerate() {
+                                    if let Some(enemy) = enemy {
+                                        slot_toggle(
+                                            ui,
+                                            &mut team.area_targets,
+                                            slot,
+                                            &unit_label(&enemy_team.name, slot, enemy),
+                                        );
+                                    }
+                                }
+                                if self.friendly_fire {
+                                    for (slot, ally) in team.units.iter().enumerate() {
+                                        if let (Some(ally), true) = (ally, slot != team.select) {
+                                            slot_toggle(
+                                                ui,
+                                                &mut team.area_allies,
+                                                slot,
+                                                &unit_label(&team.name, slot, ally),
+                                            );
+                                        }
+                                    }
+                                }
+                            });
+                            ui.checkbox(&mut team.area_roll_once, "one roll for all targets");
+                            let enemy_attack = team.attack(enemy_team);
+                            let ally_attack = team.attack(team);
+                            let mut attacker = None;
+                            let mut targets = Vec::new();
+                            let mut labels = Vec::new();
+                            for (slot, cell) in team.units.iter_mut().enumerate() {
+                                let Some(unit) = cell else { continue };
+                                if slot == team.select {
+                                    attacker = Some(unit);
+                                } else if self.friendly_fire && team.area_allies.contains(&slot) {
+                                    labels.push(unit_label(&team.name, slot, unit));
+                                    targets.push((unit, ally_attack.clone()));
+                                }
+                            }
+                            for (slot, cell) in enemy_team.units.iter_mut().enumerate() {
+                                if let (Some(unit), true) =
+                                    (cell, team.area_targets.contains(&slot))
+                                {
+                                    labels.push(unit_label(&enemy_team.name, slot, unit));
+                                    targets.push((unit, enemy_attack.clone()));
+                                }
+                            }
+                            let mut blocked = None;
+                            if let Some(attacker) = &attacker {
+                                let ranged = self.calc.is_ranged(attacker, &enemy_attack);
+                                for ((target, attack), label) in targets.iter_mut().zip(&labels) {
+                                    if let Err(e) = self.battlefield.check_attack(
+                                        attacker.position,
+                                        target.position,
+                                        ranged,
+                                        attack,
+                                    ) {
+                                        blocked = Some(format!("{} is {}", label, e));
+                                    }
+                                }
+                            }
+                            let ready = blocked.is_none() && !targets.is_empty();
+                            if let (Some(attacker), true) = (attacker, ready) {
+                                if ui.button("area attack").clicked() {
+                                    let strikes = self.calc.calculate_area(
+                                        &mut targets,
+                                        attacker,
+                                        team.area_roll_once,
+                                    );
+                                    self.log.push(area_attack_log(
+                                        self.round,
+                                        &unit_label(&team.name, team.select, attacker),
+                                        &labels,
+                                        &strikes,
+                                    ));
+                                    self.damages
+                                        .push(strikes.into_iter().map(DamageEffect::new).collect());
+                                }
+                            } else {
+                                ui.add_enabled(false, egui::Button::new("area attack"))
+                                    .on_disabled_hover_text(
+                                        blocked.unwrap_or_else(|| "pick some targets".to_string()),
+                                    );
+                            }
+                        });
+                        ui.collapsing("split / merge", |ui| {
+                            let Some(Some(unit)) = team.units.get(team.select) else {
+                                return;
+                            };
+                            let label = unit_label(&team.name, team.select, unit);
+                            let value = unit.value;
+                            let name = unit.name.clone();
+                            ui.horizontal(|ui| {
+                                egui::DragValue::new(&mut team.split_count)
+                                    .range(1..=(value - 1).max(1))
+                                    .suffix(" count")
+                                    .ui(ui);
+                                let empty = team.units.iter().any(Option::is_none);
+                                if ui
+                                    .add_enabled(empty && value > 1, egui::Button::new("split"))
+                                    .on_disabled_hover_text("needs an empty slot and 2 creatures")
+                                    .clicked()
+                                {
+                                    if let Ok(slot) = team.split(team.select, team.split_count) {
+                                        self.log.push(format!(
+                                            "r{} {}: split {} into slot {}",
+                                            self.round, label, team.split_count, slot
+                                        ));
+                                    }
+                                }
+                                let mut into = None;
+                                ui.menu_button("merge into", |ui| {
+                                    for (slot, other) in team.units.iter().enumerate() {
+                                        match other {
+                                            Some(other)
+                                                if slot != team.select && other.name == name =>
+                                            {
+                                                let other = unit_label(&team.name, slot, other);
+                                                if ui.button(&other).clicked() {
+                                                    into = Some((slot, other));
+                                                    ui.close_menu();
+                                                }
+                                            }
+                                            _ => {}
+                                        }
+                                    }
+                                });
+                                if let Some((into, other)) = into {
+                                    if team.merge(&self.calc, team.select, into).is_ok() {
+                                        self.log.push(format!(
+                                            "r{} {}: merged into {}",
+                                            self.round, label, other
+                                        ));
+                                        team.select = into;
+                                    }
+                                }
+                            });
+                        });
+                    } else {
+                        ui.horizontal(|ui| {
+                            let mut new_class = None;
+                            ui.add_space((team0_panel.width() - ui.spacing().combo_width) / 2.);
+                            egui::ComboBox::from_id_source(format!("{}_unit_class", team_num))
+                                .selected_text("💀")
+                                .show_ui(ui, |ui| {
+                                    for class in self.calc.classes.keys() {
+                                        ui.selectable_value(&mut new_class, Some(class), class);
+                                    }
+                                });
+                            if let Some(class) = new_class {
+                                *unit_cell = Some(Unit {
+                                    name: class.clone(),
+                                    ..Default::default()
+                                });
+                            }
                         });
                     }
                 }
@@ -853,19 +1724,16 @@ impl eframe::App for DamageCalcApp {
             ui.allocate_ui_at_rect(team0_panel, |ui| {
                 if self.team0 != self.team1 && self.team0.max(self.team1) < self.teams.len() {
                     let (l, r) = self.teams.split_at_mut(self.team0.min(self.team1) + 1);
-                    match (
+                    if let (Some(team_min), Some(team_max)) = (
                         l.last_mut(),
                         r.get_mut(self.team0.max(self.team1) - self.team0.min(self.team1) - 1),
                     ) {
-                        (Some(team_min), Some(team_max)) => {
-                            let (team, enemy_team) = if self.team0 > self.team1 {
-                                (team_max, team_min)
-                            } else {
-                                (team_min, team_max)
-                            };
-                            team_render(ui, team, 0, enemy_team);
-                        }
-                        _ => {}
+                        let (team, enemy_team) = if self.team0 > self.team1 {
+                            (team_max, team_min)
+                        } else {
+                            (team_min, team_max)
+                        };
+                        team_render(ui, team, 0, enemy_team);
                     }
                 } else {
                     ui.centered_and_justified(|ui| ui.label("select different teams"));
@@ -874,53 +1742,839 @@ impl eframe::App for DamageCalcApp {
             ui.allocate_ui_at_rect(team1_panel, |ui| {
                 if self.team0 != self.team1 && self.team0.max(self.team1) < self.teams.len() {
                     let (l, r) = self.teams.split_at_mut(self.team0.min(self.team1) + 1);
-                    match (
+                    if let (Some(team_min), Some(team_max)) = (
                         l.last_mut(),
                         r.get_mut(self.team0.max(self.team1) - self.team0.min(self.team1) - 1),
                     ) {
-                        (Some(team_min), Some(team_max)) => {
-                            let (team, enemy_team) = if self.team0 < self.team1 {
-                                (team_max, team_min)
-                            } else {
-                                (team_min, team_max)
-                            };
-                            team_render(ui, team, 1, enemy_team);
-                        }
-                        _ => {}
-                    };
+                        let (team, enemy_team) = if self.team0 < self.team1 {
+                            (team_max, team_min)
+                        } else {
+                            (team_min, team_max)
+                        };
+                        team_render(ui, team, 1, enemy_team);
+                    }
                 } else {
                     ui.centered_and_justified(|ui| ui.label("select different teams"));
                 }
             });
-            if let Some(effect) = self.damages.first_mut() {
-                if !effect.render(ui) {
-                    self.damages.remove(0);
-                }
-            }
+            self.play_damages(ui, true);
         });
+        if (self.team0, self.team1) != sides {
+            // Area targets are enemy slots, they point at other units once the enemy changes.
+            for team in self.teams.iter_mut() {
+                team.area_targets.clear();
+            }
+        }
     }
 }
 
+const HEX_SIZE: f32 = 20.;
+
+fn hex_center(origin: Pos2, (col, row): Hex) -> Pos2 {
+    let width = 3f32.sqrt() * HEX_SIZE;
+    origin
+        + Vec2::new(
+            width * (col as f32 + 0.5 * (row & 1) as f32 + 0.5),
+            HEX_SIZE * (1.5 * row as f32 + 1.),
+        )
+}
+
 impl DamageCalcApp {
+    /// The battle as the terminal sees it.
+    fn session(&self) -> Session {
+        Session {
+            calc: self.calc.clone(),
+            teams: self.teams.clone(),
+            round: self.round,
+            log: self.log.clone(),
+            battlefield: self.battlefield.clone(),
+            active: self.active,
+        }
+    }
+    fn load_session(&mut self, session: Session) {
+        self.calc = session.calc;
+        self.teams = session.teams;
+        self.round = session.round;
+        self.log = session.log;
+        self.battlefield = session.battlefield;
+        self.team0 = 0;
+        self.team1 = self.teams.len().min(2).saturating_sub(1);
+        self.active = session.active;
+        self.field_selected = None;
+    }
+    fn turns_ui(&mut self, ui: &mut Ui) {
+        let mut sides = vec![self.team0];
+        if self.team1 != self.team0 {
+            sides.push(self.team1);
+        }
+        let sides = sides
+            .into_iter()
+            .filter(|team_i| *team_i < self.teams.len())
+            .collect::<Vec<_>>();
+        let heroes = sides
+            .iter()
+            .map(|team_i| self.teams[*team_i].hero_bonus())
+            .collect::<Vec<_>>();
+        let order = self
+            .calc
+            .turn_order(
+                &sides
+                    .iter()
+                    .zip(heroes.iter())
+                    .map(|(team_i, hero)| (self.teams[*team_i].units.as_slice(), hero))
+                    .collect::<Vec<_>>(),
+            )
+            .into_iter()
+            .map(|(side, slot)| (sides[side], slot))
+            .collect::<Vec<_>>();
+
+        match self.active {
+            Some((team_i, slot)) => {
+                let label = self
+                    .teams
+                    .get(team_i)
+                    .and_then(|team| {
+                        Some(unit_label(
+                            &team.name,
+                            slot,
+                            team.units.get(slot)?.as_ref()?,
+                        ))
+                    })
+                    .unwrap_or_default();
+                ui.label(format!("turn of {}", label));
+                if ui.button("end turn").clicked() {
+                    self.active = None;
+                    if let Some(Some(unit)) = self
+                        .teams
+                        .get_mut(team_i)
+                        .and_then(|team| team.units.get_mut(slot))
+                    {
+                        unit.end_turn();
+                        if !unit.acted && unit.value > 0 {
+                            self.log
+                                .push(format!("r{} {}: takes the extra turn", self.round, label));
+                            self.active = Some((team_i, slot));
+                        }
+                    }
+                }
+            }
+            None => {
+                if ui
+                    .add_enabled(!order.is_empty(), egui::Button::new("start turn"))
+                    .on_disabled_hover_text("everyone acted, start the next round")
+                    .clicked()
+                {
+                    let (team_i, slot) = order[0];
+                    let team = &mut self.teams[team_i];
+                    let units = team.units.clone();
+                    let hero = team.hero_bonus();
+                    if let Some(Some(unit)) = team.units.get_mut(slot) {
+                        let morale = self.calc.morale(unit, &hero, &units);
+                        let label = unit_label(&team.name, slot, unit);
+                        match self.calc.start_turn(unit, morale) {
+                            Morale::Frozen => {
+                                self.log
+                                    .push(format!("r{} {}: low morale, frozen", self.round, label));
+                            }
+                            roll => {
+                                if roll == Morale::ExtraTurn {
+                                    self.log.push(format!(
+                                        "r{} {}: high morale, extra turn",
+                                        self.round, label
+                                    ));
+                                }
+                                team.select = slot;
+                                self.active = Some((team_i, slot));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        ui.separator();
+        egui::Grid::new("turn_order").striped(true).show(ui, |ui| {
+            for (team_i, slot) in order {
+                let team = &self.teams[team_i];
+                let Some(Some(unit)) = team.units.get(slot) else {
+                    continue;
+                };
+                let hero = team.hero_bonus();
+                let label = unit_label(&team.name, slot, unit);
+                if self.active == Some((team_i, slot)) {
+                    ui.strong(label);
+                } else {
+                    ui.label(label);
+                }
+                if let Some(stats) = self.calc.effective_stats(unit, &hero) {
+                    ui.label(format!("spd {}", stats.speed));
+                }
+                ui.label(format!(
+                    "mor {}",
+                    self.calc.morale(unit, &hero, &team.units)
+                ));
+                ui.end_row();
+            }
+        });
+    }
+
+    fn team_combo(ui: &mut Ui, id: &str, teams: &[Team], selected: &mut usize) {
+        egui::ComboBox::from_id_source(id)
+            .selected_text(teams.get(*selected).map_or("-", |team| team.name.as_str()))
+            .show_ui(ui, |ui| {
+                for (i, team) in teams.iter().enumerate() {
+                    ui.selectable_value(selected, i, &team.name);
+                }
+            });
+    }
+
+    fn encounter_ui(&mut self, ui: &mut Ui) {
+        egui::Grid::new("encounter_teams").show(ui, |ui| {
+            for (label, id) in [("players", 0), ("enemies", 1)] {
+                let selected = match id {
+                    0 => &mut self.encounter_teams.0,
+                    _ => &mut self.encounter_teams.1,
+                };
+                ui.label(label);
+                Self::team_combo(ui, label, &self.teams, selected);
+                if let Some(team) = self.teams.get(*selected) {
+                    ui.label(format!("{} pts", team_cost(&self.calc, team)))
+                        .on_hover_ui(|ui| {
+                            for unit in team.units.iter().flatten() {
+                                let cost = self.calc.cost(&unit.name).unwrap_or(0);
+                                ui.label(format!(
+                                    "{} x {}: {} pts",
+                                    unit.value,
+                                    unit.name,
+                                    unit.value * cost
+                                ));
+                            }
+                        });
+                }
+                ui.end_row();
+            }
+        });
+        ui.horizontal(|ui| {
+            egui::DragValue::new(&mut self.encounter_runs)
+                .range(1..=10000)
+                .suffix(" fights")
+                .ui(ui);
+            let (players, enemies) = self.encounter_teams;
+            if let (Some(players), Some(enemies)) =
+                (self.teams.get(players), self.teams.get(enemies))
+            {
+                if ui
+                    .button("predict")
+                    .on_hover_text("plays the fight out with random targets, spells not included")
+                    .clicked()
+                {
+                    self.difficulty = Some(difficulty(
+                        &self.calc,
+                        players,
+                        enemies,
+                        self.encounter_runs,
+                    ));
+                }
+            }
+        });
+        if let Some(difficulty) = &self.difficulty {
+            ui.heading(difficulty.rating());
+            ui.label(format!(
+                "players win {:.0}% of {} fights in {:.1} rounds, losing {:.0}% of their points",
+                difficulty.win_rate * 100.0,
+                difficulty.runs,
+                difficulty.avg_rounds,
+                difficulty.avg_losses * 100.0
+            ));
+        }
+        ui.separator();
+        ui.horizontal(|ui| {
+            egui::DragValue::new(&mut self.encounter_budget)
+                .range(1..=i64::MAX)
+                .suffix(" pts")
+                .ui(ui);
+            if ui
+                .button("generate enemies")
+                .on_hover_text(format!("a new team of up to {} stacks", self.units_count))
+                .clicked()
+            {
+                let mut team = generate(&self.calc, self.encounter_budget, self.units_count);
+                team.name = format!("encounter {}", self.teams.len());
+                self.teams.push(team);
+                self.encounter_teams.1 = self.teams.len() - 1;
+                self.difficulty = None;
+            }
+        });
+    }
+
+    fn compare_ui(&mut self, ui: &mut Ui) {
+        let mut classes = self.calc.classes.keys().cloned().collect::<Vec<_>>();
+        classes.sort();
+        if !self.calc.classes.contains_key(&self.compare_reference) {
+            self.compare_reference = classes.first().cloned().unwrap_or_default();
+        }
+        ui.horizontal(|ui| {
+            ui.label("against");
+            egui::ComboBox::from_id_source("compare_reference")
+                .selected_text(&self.compare_reference)
+                .show_ui(ui, |ui| {
+                    for class in classes.iter() {
+                        ui.selectable_value(&mut self.compare_reference, class.clone(), class);
+                    }
+                });
+            egui::TextEdit::singleline(&mut self.compare_filter)
+                .hint_text("search names and descriptions")
+                .ui(ui);
+        });
+        // Ratings are simulated attacks, the terrain bonuses change them too.
+        let key = (
+            self.calc.generation(),
+            self.calc.terrain.clone(),
+            self.compare_reference.clone(),
+        );
+        if self.ratings_for.as_ref() != Some(&key) {
+            self.ratings = classes
+                .iter()
+                .filter_map(|class| {
+                    let rating = self.calc.rating(class, &self.compare_reference, 20)?;
+                    Some((class.clone(), rating))
+                })
+                .collect();
+            self.ratings_for = Some(key);
+        }
+
+        let filter = self.compare_filter.to_lowercase();
+        let mut rows = self
+            .ratings
+            .iter()
+            .filter(|(class, _)| {
+                class.to_lowercase().contains(&filter)
+                    || self
+                        .calc
+                        .classes
+                        .get(class)
+                        .is_some_and(|stats| stats.desc.to_lowercase().contains(&filter))
+            })
+            .collect::<Vec<_>>();
+        let (column, descending) = self.compare_sort;
+        rows.sort_by(|(a_class, a), (b_class, b)| {
+            let order = match column {
+                RatingColumn::Class => a_class.cmp(b_class),
+                column => column
+                    .value(a)
+                    .partial_cmp(&column.value(b))
+                    .unwrap_or(std::cmp::Ordering::Equal),
+            };
+            if descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("compare_classes")
+                .striped(true)
+                .show(ui, |ui| {
+                    for (header, label, hint) in RatingColumn::ALL {
+                        let (sorted, descending) = self.compare_sort;
+                        let arrow = match descending {
+                            true => " ⏷",
+                            false => " ⏶",
+                        };
+                        let text = match sorted == header {
+                            true => format!("{}{}", label, arrow),
+                            false => label.to_string(),
+                        };
+                        if ui
+                            .selectable_label(sorted == header, text)
+                            .on_hover_text(hint)
+                            .clicked()
+                        {
+                            // Numbers start from the biggest, names from A.
+                            self.compare_sort = match sorted == header {
+                                true => (header, !descending),
+                                false => (header, header != RatingColumn::Class),
+                            };
+                        }
+                    }
+                    ui.end_row();
+                    for (class, rating) in rows {
+                        let desc = self
+                            .calc
+                            .classes
+                            .get(class)
+                            .map(|stats| stats.desc.as_str())
+                            .unwrap_or_default();
+                        ui.label(class).on_hover_text(desc);
+                        ui.label(format!("{:.1}", rating.effective_health));
+                        ui.label(format!("{:.1}", rating.avg_damage));
+                        ui.label(format!("{:.3}", rating.damage_per_health));
+                        ui.label(format!("{:.1}", rating.power));
+                        ui.label(rating.cost.to_string());
+                        ui.end_row();
+                    }
+                });
+        });
+    }
+    fn battlefield_ui(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            egui::DragValue::new(&mut self.battlefield.width)
+                .range(1..=40)
+                .suffix(" wide")
+                .ui(ui);
+            egui::DragValue::new(&mut self.battlefield.height)
+                .range(1..=40)
+                .suffix(" high")
+                .ui(ui);
+            egui::DragValue::new(&mut self.battlefield.long_range_distance)
+                .range(1..=i32::MAX)
+                .prefix("long range > ")
+                .ui(ui);
+            ui.toggle_value(&mut self.edit_obstacles, "edit obstacles");
+        });
+
+        let mut teams = vec![(self.team0, Color32::LIGHT_BLUE)];
+        if self.team1 != self.team0 {
+            teams.push((self.team1, Color32::LIGHT_RED));
+        }
+        let mut tokens = Vec::new();
+        for (team_i, color) in teams {
+            if let Some(team) = self.teams.get(team_i) {
+                for (slot, unit) in team.units.iter().enumerate() {
+                    if let Some(Some(position)) = unit.as_ref().map(|unit| unit.position) {
+                        tokens.push((team_i, slot, position, color));
+                    }
+                }
+            }
+        }
+        let occupied = tokens.iter().map(|t| t.2).collect::<Vec<_>>();
+        let selected = self
+            .field_selected
+            .and_then(|(team_i, slot)| self.teams.get(team_i)?.units.get(slot)?.as_ref());
+        let mut take_off = false;
+        ui.horizontal(|ui| match selected {
+            Some(unit) => {
+                ui.label(format!("moving {}", unit.name));
+                take_off = unit.position.is_some() && ui.button("take off the field").clicked();
+            }
+            None => {
+                ui.label("select a unit to place it");
+            }
+        });
+        let selected = self.field_selected.and_then(|(team_i, slot)| {
+            let team = self.teams.get(team_i)?;
+            Some((team.units.get(slot)?.as_ref()?, team.hero_bonus()))
+        });
+        let reachable = match selected {
+            Some((unit, hero)) => match (unit.position, self.calc.effective_stats(unit, &hero)) {
+                (Some(position), Some(stats)) => {
+                    self.battlefield.reachable(position, stats.speed, &occupied)
+                }
+                _ => Default::default(),
+            },
+            None => Default::default(),
+        };
+
+        let width = 3f32.sqrt() * HEX_SIZE;
+        let (rect, resp) = ui.allocate_exact_size(
+            Vec2::new(
+                width * (self.battlefield.width as f32 + 0.5),
+                HEX_SIZE * (1.5 * self.battlefield.height as f32 + 0.5),
+            ),
+            Sense::click(),
+        );
+        let painter = ui.painter_at(rect);
+        let mut clicked = None;
+        let pointer = resp.interact_pointer_pos();
+        for row in 0..self.battlefield.height {
+            for col in 0..self.battlefield.width {
+                let hex = (col, row);
+                let center = hex_center(rect.min, hex);
+                let points = (0..6)
+                    .map(|i| {
+                        let angle = (60. * i as f32 + 30.).to_radians();
+                        center + Vec2::new(angle.cos(), angle.sin()) * HEX_SIZE
+                    })
+                    .collect();
+                let fill = if self.battlefield.is_obstacle(hex) {
+                    Color32::DARK_GRAY
+                } else if reachable.contains(&hex) {
+                    Color32::DARK_GREEN
+                } else {
+                    ui.visuals().extreme_bg_color
+                };
+                painter.add(Shape::convex_polygon(
+                    points,
+                    fill,
+                    Stroke::new(1., ui.visuals().window_stroke.color),
+                ));
+                if pointer.is_some_and(|p| p.distance(center) < width / 2.) {
+                    clicked = Some(hex);
+                }
+            }
+        }
+        for (team_i, slot, position, color) in tokens.iter() {
+            let center = hex_center(rect.min, *position);
+            let stroke = if self.field_selected == Some((*team_i, *slot)) {
+                Stroke::new(self.style.line_size, Color32::WHITE)
+            } else {
+                Stroke::new(self.style.line_size, *color)
+            };
+            painter.circle(
+                center,
+                HEX_SIZE * 0.75,
+                self.style.box_colors[slot % self.style.box_colors.len()],
+                stroke,
+            );
+            if let Some(Some(unit)) = self.teams[*team_i].units.get(*slot) {
+                painter.text(
+                    center,
+                    Align2::CENTER_CENTER,
+                    unit.value,
+                    FontId::proportional(HEX_SIZE * 0.6),
+                    Color32::BLACK,
+                );
+            }
+        }
+
+        if take_off {
+            if let Some((team_i, slot)) = self.field_selected {
+                if let Some(Some(unit)) = self
+                    .teams
+                    .get_mut(team_i)
+                    .and_then(|t| t.units.get_mut(slot))
+                {
+                    unit.position = None;
+                }
+            }
+        }
+        let Some(hex) = clicked.filter(|_| resp.clicked()) else {
+            return;
+        };
+        if self.edit_obstacles {
+            if !occupied.contains(&hex) {
+                self.battlefield.toggle_obstacle(hex);
+            }
+        } else if let Some((team_i, slot, _, _)) = tokens.iter().find(|t| t.2 == hex) {
+            self.field_selected = Some((*team_i, *slot));
+            self.teams[*team_i].select = *slot;
+        } else if let Some((team_i, slot)) = self.field_selected {
+            if let Some(Some(unit)) = self
+                .teams
+                .get_mut(team_i)
+                .and_then(|t| t.units.get_mut(slot))
+            {
+                if (unit.position.is_none() && !self.battlefield.is_obstacle(hex))
+                    || reachable.contains(&hex)
+                {
+                    unit.position = Some(hex);
+                }
+            }
+        }
+    }
+    fn attack_mode_ui(
+        ui: &mut Ui,
+        mode: &mut AttackMode,
+        long_range: &mut bool,
+        obstacle: &mut bool,
+        team_num: usize,
+        shooter: bool,
+    ) {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source(format!("{}_attack_mode", team_num))
+                .selected_text(format!("{:?}", mode))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(mode, AttackMode::Melee, "Melee");
+                    ui.add_enabled_ui(shooter, |ui| {
+                        ui.selectable_value(mode, AttackMode::Ranged, "Ranged");
+                    });
+                });
+            if shooter && *mode == AttackMode::Ranged {
+                ui.checkbox(long_range, "long range");
+                ui.checkbox(obstacle, "obstacle");
+            }
+        });
+    }
+    fn effects_ui(ui: &mut Ui, unit: &mut Unit, team_num: usize, new_effect: &mut StatusEffect) {
+        ui.horizontal_wrapped(|ui| {
+            let mut remove = None;
+            for (i, effect) in unit.effects.iter().enumerate() {
+                if ui
+                    .small_button(format!("{} ({})", effect.name, effect.rounds))
+                    .on_hover_text(format!(
+                        "{}\nmiddle click to remove",
+                        effect_summary(effect)
+                    ))
+                    .middle_clicked()
+                {
+                    remove = Some(i);
+                }
+            }
+            if let Some(i) = remove {
+                unit.effects.remove(i);
+            }
+        });
+        egui::CollapsingHeader::new("new effect")
+            .id_source(format!("{}_new_effect", team_num))
+            .show(ui, |ui| {
+                ui.text_edit_singleline(&mut new_effect.name);
+                ui.horizontal(|ui| {
+                    egui::DragValue::new(&mut new_effect.rounds)
+                        .range(1..=i32::MAX)
+                        .suffix(" rounds")
+                        .ui(ui);
+                    egui::ComboBox::from_id_source(format!("{}_new_effect_stacking", team_num))
+                        .selected_text(format!("{:?}", new_effect.stacking))
+                        .show_ui(ui, |ui| {
+                            for stacking in [Stacking::Refresh, Stacking::Replace, Stacking::Stack]
+                            {
+                                ui.selectable_value(
+                                    &mut new_effect.stacking,
+                                    stacking,
+                                    format!("{:?}", stacking),
+                                );
+                            }
+                        });
+                });
+                egui::Grid::new(format!("{}_new_effect_grid", team_num)).show(ui, |ui| {
+                    let delta = &mut new_effect.delta;
+                    let percent = &mut new_effect.percent;
+                    for (name, delta, percent) in [
+                        ("atk:", &mut delta.attack, &mut percent.attack),
+                        ("def:", &mut delta.defense, &mut percent.defense),
+                        ("hp:", &mut delta.health, &mut percent.health),
+                        ("dmg_:", &mut delta.min_dmg, &mut percent.min_dmg),
+                        ("dmg[]:", &mut delta.max_dmg, &mut percent.max_dmg),
+                        ("luck:", &mut delta.luck, &mut percent.luck),
+                        ("lead:", &mut delta.leadership, &mut percent.leadership),
+                        ("mor:", &mut delta.morale, &mut percent.morale),
+                        ("spd:", &mut delta.speed, &mut percent.speed),
+                    ] {
+                        ui.label(name);
+                        egui::DragValue::new(delta).ui(ui);
+                        egui::DragValue::new(percent).suffix("%").ui(ui);
+                        ui.end_row();
+                    }
+                });
+                if ui
+                    .add_enabled(!new_effect.name.is_empty(), egui::Button::new("add"))
+                    .clicked()
+                {
+                    unit.add_effect(new_effect.clone());
+                }
+            });
+    }
+    fn shields_ui(ui: &mut Ui, unit: &mut Unit, team_num: usize, new_shield: &mut Shield) {
+        ui.horizontal_wrapped(|ui| {
+            let mut remove = None;
+            for (i, shield) in unit.shields.iter().enumerate() {
+                if ui
+                    .small_button(format!("🛡 {} {}", shield.source, shield.amount))
+                    .on_hover_text(format!(
+                        "{}\nmiddle click to remove",
+                        shield_summary(shield)
+                    ))
+                    .middle_clicked()
+                {
+                    remove = Some(i);
+                }
+            }
+            if let Some(i) = remove {
+                unit.shields.remove(i);
+            }
+        });
+        egui::CollapsingHeader::new("new shield")
+            .id_source(format!("{}_new_shield", team_num))
+            .show(ui, |ui| {
+                ui.text_edit_singleline(&mut new_shield.source);
+                ui.horizontal(|ui| {
+                    egui::DragValue::new(&mut new_shield.amount)
+                        .range(1..=i32::MAX)
+                        .suffix(" hp")
+                        .ui(ui);
+                    let mut rounds = new_shield.rounds.unwrap_or(0);
+                    egui::DragValue::new(&mut rounds)
+                        .range(0..=i32::MAX)
+                        .custom_formatter(|rounds, _| {
+                            if rounds == 0. {
+                                "until used up".to_string()
+                            } else {
+                                format!("{} rounds", rounds)
+                            }
+                        })
+                        .ui(ui);
+                    new_shield.rounds = (rounds > 0).then_some(rounds);
+                    egui::ComboBox::from_id_source(format!("{}_new_shield_type", team_num))
+                        .selected_text(match new_shield.damage_type {
+                            Some(damage_type) => format!("{:?}", damage_type),
+                            None => "any damage".to_string(),
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut new_shield.damage_type, None, "any damage");
+                            for damage_type in
+                                [DamageType::Physical, DamageType::Fire, DamageType::Ice]
+                            {
+                                ui.selectable_value(
+                                    &mut new_shield.damage_type,
+                                    Some(damage_type),
+                                    format!("{:?}", damage_type),
+                                );
+                            }
+                        });
+                });
+                if ui
+                    .add_enabled(
+                        !new_shield.source.is_empty() && new_shield.amount > 0,
+                        egui::Button::new("add"),
+                    )
+                    .clicked()
+                {
+                    unit.shields.push(new_shield.clone());
+                }
+            });
+    }
+    /// Health of the top creature with the shields drawn over it.
+    fn hp_bar(ui: &mut Ui, unit: &Unit, stats: Option<Stats>) {
+        let Some(stats) = stats else {
+            egui::ProgressBar::new(1.).text("-").ui(ui);
+            return;
+        };
+        let shield = unit.shield(None);
+        let mut text = format!("{}/{}", stats.health - unit.damage_left, stats.health);
+        if shield > 0 {
+            text += &format!(" +{}", shield);
+        }
+        let bar = egui::ProgressBar::new(1. - unit.damage_left as f32 / stats.health as f32)
+            .text(text)
+            .ui(ui);
+        if shield > 0 {
+            let mut rect = bar.rect;
+            rect.set_width(rect.width() * (shield as f32 / stats.health.max(1) as f32).min(1.));
+            ui.painter().rect_filled(
+                rect,
+                rect.height() / 2.,
+                Color32::from_rgba_unmultiplied(120, 170, 255, 60),
+            );
+            bar.on_hover_text(
+                unit.shields
+                    .iter()
+                    .map(shield_summary)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+        }
+    }
+    fn hero_ui(ui: &mut Ui, hero: &mut Option<Hero>, team_num: usize) {
+        egui::CollapsingHeader::new(match hero {
+            Some(hero) => format!("hero {}", hero.name),
+            None => "hero".to_string(),
+        })
+        .id_source(format!("{}_hero", team_num))
+        .show(ui, |ui| {
+            let Some(current) = hero else {
+                if ui.button("add hero").clicked() {
+                    *hero = Some(Hero::default());
+                }
+                return;
+            };
+            let mut remove_hero = false;
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut current.name);
+                remove_hero = ui
+                    .button("remove")
+                    .on_hover_text("middle click")
+                    .middle_clicked();
+            });
+            DamageCalcApp::bonus_grid(ui, &mut current.stats, format!("{}_hero_grid", team_num));
+            let mut remove = None;
+            for (i, artifact) in current.artifacts.iter_mut().enumerate() {
+                egui::CollapsingHeader::new(format!("artifact {}", artifact.name))
+                    .id_source(format!("{}_artifact_{}", team_num, i))
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut artifact.name);
+                            if ui
+                                .button("remove")
+                                .on_hover_text("middle click")
+                                .middle_clicked()
+                            {
+                                remove = Some(i);
+                            }
+                        });
+                        DamageCalcApp::bonus_grid(
+                            ui,
+                            &mut artifact.stats,
+                            format!("{}_artifact_{}_grid", team_num, i),
+                        );
+                    });
+            }
+            if let Some(i) = remove {
+                current.artifacts.remove(i);
+            }
+            if ui.button("add artifact").clicked() {
+                current.artifacts.push(Artifact::default());
+            }
+            if remove_hero {
+                *hero = None;
+            }
+        });
+    }
+    fn bonus_grid(ui: &mut Ui, stats: &mut Stats, id: String) {
+        egui::Grid::new(id).num_columns(4).show(ui, |ui| {
+            for (i, (name, value)) in [
+                ("atk:", &mut stats.attack),
+                ("def:", &mut stats.defense),
+                ("hp:", &mut stats.health),
+                ("dmg_:", &mut stats.min_dmg),
+                ("dmg[]:", &mut stats.max_dmg),
+                ("luck:", &mut stats.luck),
+                ("lead:", &mut stats.leadership),
+                ("mor:", &mut stats.morale),
+                ("spd:", &mut stats.speed),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                ui.label(name);
+                egui::DragValue::new(value).ui(ui);
+                if i % 2 == 1 {
+                    ui.end_row();
+                }
+            }
+        });
+    }
     fn select_column(ui: &mut Ui, team: &Team, style: &Style) -> Option<usize> {
         let mut sel = None;
         ui.vertical_centered(|ui| {
             for (i, unit) in team.units.iter().enumerate() {
-                let press = DamageCalcApp::select_box(
+                let resp = DamageCalcApp::select_box(
                     ui,
                     style.box_colors[i % style.box_colors.len()],
                     i == team.select,
-                    unit.is_none(),
+                    unit.as_ref()
+                        .map(|unit| format!("{}/{}", unit.value, unit.initial)),
                     style,
                 );
-                if press {
+                if let Some(unit) = unit {
+                    resp.clone().on_hover_text(format!(
+                        "{}\n{}/{} alive",
+                        unit.name, unit.value, unit.initial
+                    ));
+                }
+                if resp.clicked() {
                     sel = Some(i);
                 }
             }
         });
         sel
     }
-    fn select_box(ui: &mut Ui, color: Color32, selected: bool, x: bool, style: &Style) -> bool {
+    fn select_box(
+        ui: &mut Ui,
+        color: Color32,
+        selected: bool,
+        alive: Option<String>,
+        style: &Style,
+    ) -> Response {
         let (rect, resp) = ui.allocate_exact_size(Vec2::splat(style.box_size), Sense::click());
         if selected {
             ui.painter().rect(
@@ -932,7 +2586,15 @@ impl DamageCalcApp {
         } else {
             ui.painter().rect_filled(rect, Rounding::ZERO, color);
         }
-        if x {
+        if let Some(alive) = alive {
+            ui.painter().text(
+                rect.center(),
+                Align2::CENTER_CENTER,
+                alive,
+                FontId::proportional(style.box_size / 4.),
+                Color32::BLACK,
+            );
+        } else {
             ui.painter().line_segment(
                 [rect.right_top(), rect.left_bottom()],
                 Stroke::new(style.line_size, Color32::WHITE),
@@ -942,35 +2604,63 @@ impl DamageCalcApp {
                 Stroke::new(style.line_size, Color32::WHITE),
             );
         }
-        resp.clicked()
+        resp
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
-struct Team {
-    name: String,
-    select: usize,
-    units: Vec<Option<Unit>>,
-    percent: i32,
-    retaliation: bool,
-    second_select: usize,
+#[derive(PartialEq, Default)]
+enum JsonTab {
+    #[default]
+    Classes,
+    Spells,
+    Session,
 }
-impl Team {
-    fn new(count: usize) -> Self {
-        let mut units = Vec::with_capacity(count);
-        for _ in 0..count {
-            units.push(None);
-        }
-        Self {
-            name: "team".to_string(),
-            select: 0,
-            units: units,
-            percent: 100,
-            retaliation: false,
-            second_select: 0,
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, Default)]
+enum RatingColumn {
+    #[default]
+    Class,
+    EffectiveHealth,
+    AvgDamage,
+    DamagePerHealth,
+    Power,
+    Cost,
+}
+
+impl RatingColumn {
+    const ALL: [(Self, &'static str, &'static str); 6] = [
+        (Self::Class, "class", "hover a class for its description"),
+        (
+            Self::EffectiveHealth,
+            "eff. hp",
+            "health with defence and resistance",
+        ),
+        (Self::AvgDamage, "avg dmg", "average roll of one creature"),
+        (
+            Self::DamagePerHealth,
+            "dmg/hp",
+            "damage dealt to the reference defender per point of own health",
+        ),
+        (
+            Self::Power,
+            "power",
+            "geometric mean of damage and toughness",
+        ),
+        (Self::Cost, "cost", "encounter points per creature"),
+    ];
+
+    fn value(self, rating: &Rating) -> f32 {
+        match self {
+            Self::Class => 0.0,
+            Self::EffectiveHealth => rating.effective_health,
+            Self::AvgDamage => rating.avg_damage,
+            Self::DamagePerHealth => rating.damage_per_health,
+            Self::Power => rating.power,
+            Self::Cost => rating.cost as f32,
         }
     }
 }
+
 #[derive(serde::Deserialize, serde::Serialize)]
 struct Style {
     fancy_stats: bool,
@@ -1023,16 +2713,147 @@ impl Style {
     }
 }
 
+fn effect_summary(effect: &StatusEffect) -> String {
+    let mut parts = Vec::new();
+    for (name, delta, percent) in [
+        ("atk", effect.delta.attack, effect.percent.attack),
+        ("def", effect.delta.defense, effect.percent.defense),
+        ("hp", effect.delta.health, effect.percent.health),
+        ("dmg_", effect.delta.min_dmg, effect.percent.min_dmg),
+        ("dmg[]", effect.delta.max_dmg, effect.percent.max_dmg),
+        ("luck", effect.delta.luck, effect.percent.luck),
+        ("lead", effect.delta.leadership, effect.percent.leadership),
+        ("mor", effect.delta.morale, effect.percent.morale),
+        ("spd", effect.delta.speed, effect.percent.speed),
+    ] {
+        if delta != 0 {
+            parts.push(format!("{} {:+}", name, delta));
+        }
+        if percent != 0 {
+            parts.push(format!("{} {:+}%", name, percent));
+        }
+    }
+    for ability in effect.delta.abilities.iter() {
+        parts.push(format!("{:?}", ability));
+    }
+    format!("{:?}: {}", effect.stacking, parts.join(", "))
+}
+
+/// One line per source that changes a stat, percent changes of effects last.
+fn sources_text(calc: &Calc, unit: &Unit, hero: &Stats, field: impl Fn(&Stats) -> i32) -> String {
+    let mut lines = Vec::new();
+    for (i, (source, stats)) in calc
+        .stat_sources(unit, hero)
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+    {
+        match (i, field(stats)) {
+            (0, value) => lines.push(format!("{}: {}", source, value)),
+            (_, 0) => {}
+            (_, value) => lines.push(format!("{}: {:+}", source, value)),
+        }
+    }
+    for effect in unit.effects.iter() {
+        let percent = field(&effect.percent);
+        if percent != 0 {
+            lines.push(format!("{}: {:+}%", effect.name, percent));
+        }
+    }
+    lines.join("\n")
+}
+
+fn shield_summary(shield: &Shield) -> String {
+    let mut text = format!("{}: {} hp", shield.source, shield.amount);
+    if let Some(damage_type) = shield.damage_type {
+        text += &format!(" against {:?}", damage_type);
+    }
+    if let Some(rounds) = shield.rounds {
+        text += &format!(", {} rounds", rounds);
+    }
+    text
+}
+
+fn cast_log(round: i32, spell: &str, target: &str, cast: &Cast) -> String {
+    let result = match cast {
+        Cast::Damage(strike) => format!("{} dmg, {} killed", strike.damage, strike.killed),
+        Cast::Heal(healed, revived) => format!("{} hp, {} revived", healed, revived),
+        Cast::Effect(rounds) => format!("{} rounds", rounds),
+        Cast::Resisted => "resisted".to_string(),
+    };
+    format!("r{} {} on {}: {}", round, spell, target, result)
+}
+
+fn area_attack_log(round: i32, attacker: &str, targets: &[String], strikes: &[Strike]) -> String {
+    let hits = targets
+        .iter()
+        .zip(strikes)
+        .map(|(target, strike)| {
+            format!("{} {} dmg, {} killed", target, strike.damage, strike.killed)
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "r{} {} area attack: {}; total {} dmg, {} killed",
+        round,
+        attacker,
+        hits.join("; "),
+        strikes.iter().map(|strike| strike.damage).sum::<i32>(),
+        strikes.iter().map(|strike| strike.killed).sum::<i32>()
+    )
+}
+
+fn slot_toggle(ui: &mut Ui, slots: &mut Vec<usize>, slot: usize, label: &str) {
+    let mut checked = slots.contains(&slot);
+    if ui.checkbox(&mut checked, label).changed() {
+        if checked {
+            slots.push(slot);
+        } else {
+            slots.retain(|s| *s != slot);
+        }
+    }
+}
+
+fn default_port() -> u16 {
+    7878
+}
+
+fn default_runs() -> u32 {
+    200
+}
+
+fn default_budget() -> i64 {
+    1000
+}
+
+fn attack_label(retaliation: bool) -> &'static str {
+    if retaliation {
+        "attack (retaliation)"
+    } else {
+        "attack"
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 struct DamageEffect {
     damage: i32,
     text: [String; 2],
+    retaliation: bool,
+    healing: bool,
+    breakdown: Vec<String>,
+    #[serde(skip)]
     init: bool,
+    #[serde(skip)]
     start_time: f64,
+    /// Already sent to the spectators.
+    #[serde(skip)]
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    published: bool,
 }
 
 const ANIMATION_TIME: f64 = 5.;
 impl DamageEffect {
-    fn render(&mut self, ui: &mut Ui) -> bool {
+    /// Without `detailed` the breakdown, which tells a lot about the stats, is left out.
+    fn render(&mut self, ui: &mut Ui, rect: Rect, detailed: bool) -> bool {
         let time = ui.input(|i| i.time);
         if !self.init {
             self.init = true;
@@ -1043,8 +2864,8 @@ impl DamageEffect {
             return false;
         }
         if p > 0.5 {
-            let spos = ui.max_rect().center();
-            let epos = ui.max_rect().center_bottom();
+            let spos = rect.center();
+            let epos = rect.center_bottom();
             ui.ctx().request_repaint();
             let p = (p - 0.5) * 2.;
             ui.painter().text(
@@ -1052,7 +2873,7 @@ impl DamageEffect {
                 Align2::CENTER_CENTER,
                 self.damage,
                 FontId::proportional(55.),
-                Color32::RED,
+                self.color(),
             );
             for i in 0..2 {
                 let spos = spos.add(Vec2::new(0., (i + 1) as f32 * -55.));
@@ -1061,11 +2882,21 @@ impl DamageEffect {
                     Align2::CENTER_CENTER,
                     &self.text[i],
                     FontId::proportional(55.),
-                    Color32::RED,
+                    self.color(),
+                );
+            }
+            for (i, line) in self.breakdown.iter().enumerate().filter(|_| detailed) {
+                let spos = spos.add(Vec2::new(0., 45. + i as f32 * 22.));
+                ui.painter().text(
+                    spos.lerp(epos, (p * p * p) as f32),
+                    Align2::CENTER_CENTER,
+                    line,
+                    FontId::proportional(18.),
+                    self.color(),
                 );
             }
         } else {
-            let spos = ui.max_rect().center();
+            let spos = rect.center();
             ui.ctx().request_repaint();
             let disp_damage = lerp(
                 0. ..=self.damage as f32,
@@ -1076,7 +2907,7 @@ impl DamageEffect {
                 Align2::CENTER_CENTER,
                 disp_damage.to_string(),
                 FontId::proportional(55.),
-                Color32::RED,
+                self.color(),
             );
             for i in 0..2 {
                 let spos = spos.add(Vec2::new(0., (i + 1) as f32 * -55.));
@@ -1085,18 +2916,67 @@ impl DamageEffect {
                     Align2::CENTER_CENTER,
                     &self.text[i],
                     FontId::proportional(55.),
-                    Color32::RED,
+                    self.color(),
+                );
+            }
+            for (i, line) in self.breakdown.iter().enumerate().filter(|_| detailed) {
+                ui.painter().text(
+                    spos.add(Vec2::new(0., 45. + i as f32 * 22.)),
+                    Align2::CENTER_CENTER,
+                    line,
+                    FontId::proportional(18.),
+                    self.color(),
                 );
             }
         }
         true
     }
-    fn new(dmg: i32, text: [String; 2]) -> Self {
+    /// Copy without the breakdown, which tells spectators too much about the stats.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn for_spectators(&self) -> Self {
+        Self {
+            breakdown: Vec::new(),
+            ..self.clone()
+        }
+    }
+    fn heal(healed: i32, revived: i32) -> Self {
+        Self {
+            damage: healed,
+            text: [
+                if revived > 0 {
+                    format!("+{} revived", revived)
+                } else {
+                    String::new()
+                },
+                String::new(),
+            ],
+            retaliation: false,
+            healing: true,
+            breakdown: Vec::new(),
+            init: false,
+            start_time: 0.,
+            published: false,
+        }
+    }
+    fn color(&self) -> Color32 {
+        if self.healing {
+            Color32::GREEN
+        } else if self.retaliation {
+            Color32::GOLD
+        } else {
+            Color32::RED
+        }
+    }
+    fn new(strike: Strike) -> Self {
         Self {
-            damage: dmg,
-            text,
+            damage: strike.damage,
+            text: strike.text,
+            retaliation: strike.retaliation,
+            healing: false,
+            breakdown: strike.breakdown,
             init: false,
             start_time: 0.,
+            published: false,
         }
     }
 }
@@ -1,6 +1,10 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
-mod calc;
-
+#[cfg(not(target_arch = "wasm32"))]
+mod multiplayer;
+mod share;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sync;
 pub use app::DamageCalcApp;
+pub use damage_calc_core::{battlefield, calc, encounter, session};
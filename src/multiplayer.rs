@@ -0,0 +1,193 @@
+use crate::session::SpectatorView;
+use crate::sync::{random_token, Client, Server, Update};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often spectators ask for news and the GM checks for changes, in seconds.
+pub const SYNC_INTERVAL: f64 = 0.3;
+
+enum Message<E> {
+    Update(Box<Update<E>>),
+    Published(u64),
+    Error(String),
+}
+
+/// Connection of the app to a battle server, as the GM or as a spectator.
+pub struct Multiplayer<E> {
+    pub client: Client,
+    server: Option<Server>,
+    outbox: Option<Sender<(SpectatorView, Vec<E>)>>,
+    inbox: Receiver<Message<E>>,
+    stop: Arc<AtomicBool>,
+    pub status: String,
+    last_json: String,
+    last_check: f64,
+}
+
+impl<E: Serialize + DeserializeOwned + Send + 'static> Multiplayer<E> {
+    /// Starts a server on every interface and publishes to it.
+    pub fn host(port: u16) -> Result<Self, String> {
+        let server = Server::start(("0.0.0.0", port), random_token())
+            .map_err(|e| format!("can't listen on port {}: {}", port, e))?;
+        let mut multiplayer =
+            Self::join(server.addr().to_string(), Some(server.token().to_string()));
+        multiplayer.server = Some(server);
+        Ok(multiplayer)
+    }
+
+    /// Follows the server at `addr`, and writes to it when `token` is the GM's.
+    pub fn join(addr: String, token: Option<String>) -> Self {
+        let client = Client { addr, token };
+        let (messages, inbox) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let outbox = if client.token.is_some() {
+            let (outbox, published) = channel::<(SpectatorView, Vec<E>)>();
+            let client = client.clone();
+            std::thread::spawn(move || {
+                for (view, events) in published {
+                    let message = match client.publish(&view, &events) {
+                        Ok(version) => Message::Published(version),
+                        Err(e) => Message::Error(e),
+                    };
+                    if messages.send(message).is_err() {
+                        break;
+                    }
+                }
+            });
+            Some(outbox)
+        } else {
+            let (client, stop) = (client.clone(), stop.clone());
+            std::thread::spawn(move || {
+                // `None` until the server answered, what happened before joining is not replayed.
+                let mut since = None;
+                while !stop.load(Ordering::Relaxed) {
+                    let fetched = match since {
+                        None => client.join(),
+                        Some(since) => client.fetch(since),
+                    };
+                    let message = match fetched {
+                        Ok(Some(update)) => {
+                            since = Some(update.version);
+                            Message::Update(Box::new(update))
+                        }
+                        Ok(None) => Message::Published(*since.get_or_insert(0)),
+                        Err(e) => Message::Error(e),
+                    };
+                    if messages.send(message).is_err() {
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_secs_f64(SYNC_INTERVAL));
+                }
+            });
+            None
+        };
+        Self {
+            client,
+            server: None,
+            outbox,
+            inbox,
+            stop,
+            status: "connecting".to_string(),
+            last_json: String::new(),
+            last_check: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn is_gm(&self) -> bool {
+        self.outbox.is_some()
+    }
+
+    pub fn server(&self) -> Option<&Server> {
+        self.server.as_ref()
+    }
+
+    /// Latest view of the battle and every event received since the last call.
+    pub fn receive(&mut self) -> Option<(SpectatorView, Vec<E>)> {
+        let mut received: Option<(SpectatorView, Vec<E>)> = None;
+        while let Ok(message) = self.inbox.try_recv() {
+            match message {
+                Message::Update(update) => {
+                    self.status = format!("version {}", update.version);
+                    let mut events = received.map(|(_, events)| events).unwrap_or_default();
+                    events.extend(update.events);
+                    received = Some((update.view, events));
+                }
+                Message::Published(version) => self.status = format!("version {}", version),
+                Message::Error(e) => self.status = e,
+            }
+        }
+        received
+    }
+
+    /// Sends what spectators may see of the battle when it changed or `events` happened,
+    /// checks at most every `SYNC_INTERVAL`.
+    pub fn publish(&mut self, time: f64, view: impl FnOnce() -> SpectatorView, events: Vec<E>) {
+        let Some(outbox) = &self.outbox else {
+            return;
+        };
+        if events.is_empty() && time - self.last_check < SYNC_INTERVAL {
+            return;
+        }
+        self.last_check = time;
+        let view = view();
+        let json = serde_json::to_string(&view).unwrap_or_default();
+        if events.is_empty() && json == self.last_json {
+            return;
+        }
+        self.last_json = json;
+        let _ = outbox.send((view, events));
+    }
+}
+
+impl<E> Drop for Multiplayer<E> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Waits for `receive` to return something, for at most a few seconds.
+    fn received(multiplayer: &mut Multiplayer<String>) -> Option<(SpectatorView, Vec<String>)> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if let Some(received) = multiplayer.receive() {
+                return Some(received);
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        None
+    }
+
+    #[test]
+    fn spectators_join_without_old_events() {
+        let mut gm = Multiplayer::<String>::host(0).unwrap();
+        let view = SpectatorView {
+            round: 2,
+            ..Default::default()
+        };
+        gm.publish(0.0, || view.clone(), vec!["before".to_string()]);
+        let start = Instant::now();
+        while gm.status != "version 1" && start.elapsed() < Duration::from_secs(5) {
+            gm.receive();
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        let addr = gm.client.addr.clone();
+        let mut spectator = Multiplayer::<String>::join(addr, None);
+        let (joined, events) = received(&mut spectator).unwrap();
+        assert_eq!(joined.round, 2);
+        assert!(events.is_empty());
+
+        gm.publish(1.0, || view.clone(), vec!["after".to_string()]);
+        let (_, events) = received(&mut spectator).unwrap();
+        assert_eq!(events, ["after"]);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::calc::Calc;
+use crate::session::Team;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use miniz_oxide::inflate::TINFLStatus;
+
+/// Starts the url fragment of a shared battle, `#battle=<data>`.
+pub const FRAGMENT_KEY: &str = "battle=";
+
+/// Most json a link may inflate to, far above any real battle.
+const MAX_JSON: usize = 1 << 20;
+
+/// The part of a battle that fits in a link, the class library stays with each GM.
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(default)]
+pub struct SharedBattle {
+    pub teams: Vec<Team>,
+    pub round: i32,
+    pub terrain: Option<String>,
+    /// `Calc::library_hash` of the sender, if they chose to include it.
+    pub library: Option<u64>,
+}
+
+impl SharedBattle {
+    /// Fragment without the `#`, json compressed with deflate and base64 encoded.
+    pub fn to_fragment(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        let compressed = miniz_oxide::deflate::compress_to_vec(&json, 9);
+        format!("{}{}", FRAGMENT_KEY, URL_SAFE_NO_PAD.encode(compressed))
+    }
+
+    /// `None` if the fragment is not a shared battle at all.
+    pub fn from_fragment(fragment: &str) -> Option<Result<Self, String>> {
+        let data = fragment
+            .trim_start_matches('#')
+            .strip_prefix(FRAGMENT_KEY)?;
+        Some(Self::decode(data))
+    }
+
+    fn decode(data: &str) -> Result<Self, String> {
+        let compressed = URL_SAFE_NO_PAD
+            .decode(data.trim())
+            .map_err(|_| "the link is cut off or mistyped".to_string())?;
+        let json = miniz_oxide::inflate::decompress_to_vec_with_limit(&compressed, MAX_JSON)
+            .map_err(|e| match e.status {
+                TINFLStatus::HasMoreOutput => "the link is too large".to_string(),
+                _ => "the link is cut off or mistyped".to_string(),
+            })?;
+        serde_json::from_slice(&json)
+            .map_err(|e| format!("the link is from another version of the app: {}", e))
+    }
+
+    /// Fails if a unit's class is missing from `calc`.
+    pub fn check(&self, calc: &Calc) -> Result<(), String> {
+        let mut missing = self
+            .teams
+            .iter()
+            .flat_map(|team| team.units.iter().flatten())
+            .map(|unit| unit.name.as_str())
+            // Slots filled in by hand before picking a class have no name yet.
+            .filter(|name| !name.is_empty() && !calc.classes.contains_key(*name))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        missing.sort();
+        missing.dedup();
+        let mut error = format!(
+            "load the sender's class file first, these classes are missing: {}",
+            missing.join(", ")
+        );
+        if self.library.is_some_and(|hash| hash != calc.library_hash()) {
+            error += "\nyour class library differs from the sender's";
+        }
+        Err(error)
+    }
+
+    /// Warning for a battle that loads, but whose classes may have other stats.
+    pub fn library_warning(&self, calc: &Calc) -> Option<String> {
+        self.library
+            .filter(|hash| *hash != calc.library_hash())
+            .map(|_| "your class library differs from the sender's, stats may not match".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calc::Unit;
+
+    #[test]
+    fn fragment_round_trip() {
+        let classes = r#"{
+            "Peasant": {"attack": 1, "defence": 1, "min_dmg": 1, "max_dmg": 1, "health": 1, "description": ""},
+            "Angel": {"attack": 20, "defence": 20, "min_dmg": 50, "max_dmg": 50, "health": 200, "description": ""}
+        }"#;
+        let mut calc = Calc::default();
+        calc.update(classes);
+        let mut team = Team::new(3);
+        team.units[1] = Some(Unit {
+            name: "Peasant".to_string(),
+            value: 40,
+            ..Default::default()
+        });
+        team.units[2] = Some(Unit::default());
+        let shared = SharedBattle {
+            teams: vec![team],
+            round: 2,
+            terrain: None,
+            library: Some(calc.library_hash()),
+        };
+        let fragment = format!("#{}", shared.to_fragment());
+        assert!(fragment
+            .chars()
+            .skip(1)
+            .all(|c| c.is_ascii_alphanumeric() || "-_=".contains(c)));
+
+        let restored = SharedBattle::from_fragment(&fragment).unwrap().unwrap();
+        assert_eq!(restored.round, 2);
+        assert_eq!(restored.teams[0].units[1].as_ref().unwrap().value, 40);
+        assert_eq!(restored.check(&calc), Ok(()));
+        assert_eq!(restored.library_warning(&calc), None);
+        let mut reloaded = Calc::default();
+        reloaded.update(classes);
+        assert_eq!(reloaded.library_hash(), calc.library_hash());
+
+        let empty = Calc::default();
+        let error = restored.check(&empty).unwrap_err();
+        assert!(error.contains("missing: Peasant\n"));
+        assert!(error.contains("differs"));
+
+        assert!(SharedBattle::from_fragment("#dev").is_none());
+        assert!(SharedBattle::from_fragment("#battle=abc").unwrap().is_err());
+
+        let bomb = miniz_oxide::deflate::compress_to_vec(&vec![b' '; MAX_JSON + 1], 9);
+        let fragment = format!("#{}{}", FRAGMENT_KEY, URL_SAFE_NO_PAD.encode(bomb));
+        assert!(fragment.len() < 4096);
+        let error = SharedBattle::from_fragment(&fragment).unwrap().err();
+        assert_eq!(error.as_deref(), Some("the link is too large"));
+    }
+}
@@ -0,0 +1,344 @@
+//! Small http server that lets several apps on a local network follow one battle.
+//!
+//! The GM publishes a `SpectatorView` of the battle together with the events that happened since
+//! the last publish, never the stats only the GM should know. Spectators poll `GET /state?since=<version>` and get `204` while nothing changed.
+//! Writing needs the token the server was started with, reading is open to everyone.
+
+use crate::session::SpectatorView;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Events kept for spectators that poll late.
+const KEPT_EVENTS: usize = 64;
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BODY: usize = 16 << 20;
+
+/// What a spectator gets, `events` are the ones published after the version it asked for.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Update<E> {
+    pub version: u64,
+    pub view: SpectatorView,
+    pub events: Vec<E>,
+}
+
+#[derive(Default)]
+struct State {
+    version: u64,
+    view: Value,
+    /// Every event with the version it was published in.
+    events: Vec<(u64, Value)>,
+}
+
+/// Runs until dropped.
+pub struct Server {
+    addr: SocketAddr,
+    token: String,
+    stop: Arc<AtomicBool>,
+}
+
+/// Token for `Server::start`, hard enough to guess on a local network.
+pub fn random_token() -> String {
+    let mut rng = rand::thread_rng();
+    (0..16)
+        .map(|_| char::from_digit(rng.gen_range(0..36), 36).unwrap_or('0'))
+        .collect()
+}
+
+impl Server {
+    /// Listens on `addr`, port 0 picks a free one.
+    pub fn start(addr: impl ToSocketAddrs, token: String) -> std::io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let state = Arc::new(Mutex::new(State::default()));
+        {
+            let (stop, token) = (stop.clone(), token.clone());
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let (state, token) = (state.clone(), token.clone());
+                    std::thread::spawn(move || {
+                        let _ = handle(stream, &state, &token);
+                    });
+                }
+            });
+        }
+        Ok(Server { addr, token, stop })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wakes the accept loop so it sees the flag.
+        let _ = TcpStream::connect_timeout(&local(self.addr), TIMEOUT);
+    }
+}
+
+/// `0.0.0.0` can be listened on, but not connected to.
+fn local(mut addr: SocketAddr) -> SocketAddr {
+    if addr.ip().is_unspecified() {
+        addr.set_ip([127, 0, 0, 1].into());
+    }
+    addr
+}
+
+struct Request {
+    method: String,
+    path: String,
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+fn read_request(stream: &TcpStream) -> Result<Request, String> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| e.to_string())?;
+    let mut words = line.split_whitespace();
+    let (Some(method), Some(path)) = (words.next(), words.next()) else {
+        return Err("bad request line".to_string());
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+    let (mut length, mut token) = (0, None);
+    loop {
+        line.clear();
+        reader.read_line(&mut line).map_err(|e| e.to_string())?;
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => length = value.parse().map_err(|_| "bad content-length")?,
+            "authorization" => token = value.strip_prefix("Bearer ").map(str::to_string),
+            _ => {}
+        }
+    }
+    if length > MAX_BODY {
+        return Err("body too large".to_string());
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+    Ok(Request {
+        method,
+        path,
+        token,
+        body,
+    })
+}
+
+fn respond(mut stream: &TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn handle(stream: TcpStream, state: &Mutex<State>, token: &str) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let request = match read_request(&stream) {
+        Ok(request) => request,
+        Err(e) => return respond(&stream, "400 Bad Request", &Value::from(e).to_string()),
+    };
+    let (path, query) = request
+        .path
+        .split_once('?')
+        .unwrap_or((request.path.as_str(), ""));
+    match (request.method.as_str(), path) {
+        ("GET", "/state") => {
+            let since = query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("since="))
+                .and_then(|since| since.parse::<u64>().ok())
+                .unwrap_or(0);
+            let state = state.lock().unwrap_or_else(|e| e.into_inner());
+            if state.version == since {
+                return respond(&stream, "204 No Content", "");
+            }
+            let events = state
+                .events
+                .iter()
+                .filter(|(version, _)| *version > since)
+                .map(|(_, event)| event)
+                .collect::<Vec<_>>();
+            let body = serde_json::json!({
+                "version": state.version,
+                "view": state.view,
+                "events": events,
+            });
+            respond(&stream, "200 OK", &body.to_string())
+        }
+        ("POST", "/state") => {
+            if request.token.as_deref() != Some(token) {
+                return respond(
+                    &stream,
+                    "403 Forbidden",
+                    "\"only the GM can change the battle\"",
+                );
+            }
+            let Ok(Value::Object(mut body)) = serde_json::from_slice::<Value>(&request.body) else {
+                return respond(&stream, "400 Bad Request", "\"expected a json object\"");
+            };
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            state.version += 1;
+            let version = state.version;
+            if let Some(view) = body.remove("view") {
+                state.view = view;
+            }
+            if let Some(Value::Array(events)) = body.remove("events") {
+                state
+                    .events
+                    .extend(events.into_iter().map(|event| (version, event)));
+            }
+            let extra = state.events.len().saturating_sub(KEPT_EVENTS);
+            state.events.drain(..extra);
+            respond(&stream, "200 OK", &version.to_string())
+        }
+        _ => respond(&stream, "404 Not Found", "\"try GET /state\""),
+    }
+}
+
+/// Talks to a `Server`, as the GM when it has the token.
+#[derive(Clone)]
+pub struct Client {
+    pub addr: String,
+    pub token: Option<String>,
+}
+
+impl Client {
+    fn request(&self, method: &str, path: &str, body: &str) -> Result<(u16, String), String> {
+        let addr = self
+            .addr
+            .to_socket_addrs()
+            .map_err(|e| format!("{}: {}", self.addr, e))?
+            .next()
+            .ok_or_else(|| format!("{} is not an address", self.addr))?;
+        let mut stream = TcpStream::connect_timeout(&local(addr), TIMEOUT)
+            .map_err(|e| format!("can't reach {}: {}", self.addr, e))?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let auth = match &self.token {
+            Some(token) => format!("Authorization: Bearer {}\r\n", token),
+            None => String::new(),
+        };
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            self.addr,
+            auth,
+            body.len(),
+            body
+        )
+        .map_err(|e| e.to_string())?;
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .map_err(|e| e.to_string())?;
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or("server sent no headers")?;
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or("server sent no status")?;
+        Ok((status, body.to_string()))
+    }
+
+    /// `None` while the battle is still at version `since`.
+    pub fn fetch<E: DeserializeOwned>(&self, since: u64) -> Result<Option<Update<E>>, String> {
+        match self.request("GET", &format!("/state?since={}", since), "")? {
+            (204, _) => Ok(None),
+            (200, body) => serde_json::from_str(&body)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+            (status, body) => Err(format!("server answered {}: {}", status, body)),
+        }
+    }
+
+    /// Latest view of the battle without the events that led up to it, for a spectator that
+    /// just joined. `None` while nothing was published.
+    pub fn join<E: DeserializeOwned>(&self) -> Result<Option<Update<E>>, String> {
+        let mut update = self.fetch(0)?;
+        if let Some(update) = update.as_mut() {
+            update.events.clear();
+        }
+        Ok(update)
+    }
+
+    /// Replaces the view of the battle and appends `events`, returns the new version.
+    pub fn publish<E: Serialize>(&self, view: &SpectatorView, events: &[E]) -> Result<u64, String> {
+        let body = serde_json::json!({"view": view, "events": events}).to_string();
+        match self.request("POST", "/state", &body)? {
+            (200, version) => version.trim().parse().map_err(|_| version),
+            (status, body) => Err(format!("server answered {}: {}", status, body)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gm_and_spectators() {
+        let server = Server::start("127.0.0.1:0", random_token()).unwrap();
+        let addr = server.addr().to_string();
+        let gm = Client {
+            addr: addr.clone(),
+            token: Some(server.token().to_string()),
+        };
+        let spectator = Client { addr, token: None };
+
+        assert!(spectator.fetch::<String>(0).unwrap().is_none());
+        let mut view = SpectatorView {
+            round: 3,
+            ..Default::default()
+        };
+        assert!(spectator.publish(&view, &["cheat"]).is_err());
+        assert_eq!(gm.publish(&view, &["first"]), Ok(1));
+        view.round = 4;
+        assert_eq!(gm.publish(&view, &["second", "third"]), Ok(2));
+
+        let update = spectator.fetch::<String>(0).unwrap().unwrap();
+        assert_eq!((update.version, update.view.round), (2, 4));
+        assert_eq!(update.events, ["first", "second", "third"]);
+        let update = spectator.fetch::<String>(1).unwrap().unwrap();
+        assert_eq!(update.events, ["second", "third"]);
+        assert!(spectator.fetch::<String>(2).unwrap().is_none());
+
+        let late = spectator.join::<String>().unwrap().unwrap();
+        assert_eq!((late.version, late.view.round), (2, 4));
+        assert!(late.events.is_empty());
+        assert_eq!(gm.publish(&view, &["fourth"]), Ok(3));
+        let update = spectator.fetch::<String>(late.version).unwrap().unwrap();
+        assert_eq!(update.events, ["fourth"]);
+    }
+}